@@ -0,0 +1,288 @@
+//! Alarms on the system timer. Any number of software alarms (up to
+//! `MAX_ALARMS`), one-shot or periodic, are kept in a min-heap ordered by
+//! deadline; one compare channel (C1 or C3, the ones the GPU leaves to the
+//! ARM) is always programmed for the earliest of them, and its interrupt runs
+//! the callbacks that are due. Callbacks run in interrupt context, so they
+//! must be short and must not yield. Alarms set before `alarm_init()` wait
+//! until it is called.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::time::*;
+use crate::interrupts::*;
+
+const SYSTEM_TIMER_CS: u32 = 0x20003000;
+const SYSTEM_TIMER_C0: u32 = 0x2000300C;
+
+pub const MAX_ALARMS: usize = 32;
+
+/// Compare values are 32 bits; later deadlines are reached in steps.
+const MAX_STEP_US: u64 = 1 << 31;
+
+/// A compare value must lie ahead of the counter when written, or it only
+/// matches after the counter wraps. Due deadlines are set this far ahead.
+const MIN_LEAD_US: u64 = 2;
+
+/// System timer compare channels free for the ARM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareChannel {
+    C1 = 1,
+    C3 = 3,
+}
+
+impl CompareChannel {
+    /// Returns the compare register of the channel.
+    fn register(self) -> u32 {
+        SYSTEM_TIMER_C0 + 4 * self as u32
+    }
+
+    /// Returns the peripheral IRQ number of the channel, which is also its
+    /// match bit in `SYSTEM_TIMER_CS`.
+    pub fn irq(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Callback run when an alarm fires; receives the argument it was set with.
+pub type AlarmHandler = unsafe fn(u32);
+
+/// Identifies a pending alarm, e.g. to cancel it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlarmId {
+    slot: u8,
+    generation: u32,
+}
+
+/// Errors reported by the alarm service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmError {
+    /// `MAX_ALARMS` alarms are already pending.
+    Full,
+    /// A periodic alarm needs a non-zero period.
+    ZeroPeriod,
+    /// The deadline lies beyond the range of `Instant`.
+    TooFar,
+}
+
+#[derive(Clone, Copy)]
+struct Alarm {
+    deadline: Instant,
+    /// `Some` for periodic alarms.
+    period: Option<Duration>,
+    handler: AlarmHandler,
+    arg: u32,
+}
+
+static mut SLOTS: [Option<Alarm>; MAX_ALARMS] = [None; MAX_ALARMS];
+/// Bumped whenever a slot is reused so stale `AlarmId`s are ignored.
+static mut GENERATIONS: [u32; MAX_ALARMS] = [0; MAX_ALARMS];
+
+/// Slot numbers of pending alarms as a min-heap on their deadlines.
+static mut HEAP: [u8; MAX_ALARMS] = [0; MAX_ALARMS];
+static mut HEAP_LEN: usize = 0;
+
+static mut CHANNEL: Option<CompareChannel> = None;
+
+/// Starts the alarm service on compare `channel`; the other channel stays
+/// free. Routes its interrupt.
+/// Precondition: interrupts are set up with `interrupt_init()`.
+pub unsafe fn alarm_init(channel: CompareChannel) {
+    CHANNEL = Some(channel);
+    timer_compare_clear(channel);
+    alarm_program();
+    irq_enable(channel.irq());
+}
+
+/// Sets compare `channel` to fire when the low 32 bits of the system timer
+/// reach `at`.
+pub unsafe fn timer_compare_set(channel: CompareChannel, at: u32) {
+    dsb();
+    put32(channel.register(), at);
+    dsb();
+}
+
+/// Returns whether compare `channel` has matched since it was last cleared.
+pub unsafe fn timer_compare_pending(channel: CompareChannel) -> bool {
+    bit_is_set(get32(SYSTEM_TIMER_CS), channel as u8)
+}
+
+/// Acknowledges a match of compare `channel`.
+pub unsafe fn timer_compare_clear(channel: CompareChannel) {
+    dsb();
+    put32(SYSTEM_TIMER_CS, bit_set(0, channel as u8));
+    dsb();
+}
+
+/// Runs `handler(arg)` once at `deadline`.
+pub unsafe fn alarm_at(deadline: Instant, handler: AlarmHandler, arg: u32) -> Result<AlarmId, AlarmError> {
+    alarm_add(Alarm { deadline, period: None, handler, arg })
+}
+
+/// Runs `handler(arg)` once, `after` from now.
+pub unsafe fn alarm_once(after: Duration, handler: AlarmHandler, arg: u32) -> Result<AlarmId, AlarmError> {
+    let deadline = Instant::now().checked_add(after).ok_or(AlarmError::TooFar)?;
+    alarm_at(deadline, handler, arg)
+}
+
+/// Runs `handler(arg)` every `period`, starting one period from now. Missed
+/// periods (e.g. while interrupts were off) are skipped, not made up.
+pub unsafe fn alarm_periodic(period: Duration, handler: AlarmHandler, arg: u32) -> Result<AlarmId, AlarmError> {
+    if period.is_zero() {
+        return Err(AlarmError::ZeroPeriod);
+    }
+    let deadline = Instant::now().checked_add(period).ok_or(AlarmError::TooFar)?;
+    alarm_add(Alarm { deadline, period: Some(period), handler, arg })
+}
+
+/// Cancels alarm `id`. Returns `false` if it already fired (one-shot) or was
+/// cancelled.
+#[allow(static_mut_refs)]
+pub unsafe fn alarm_cancel(id: AlarmId) -> bool {
+    let slot = id.slot as usize;
+    alarm_lock();
+    let pending = GENERATIONS[slot] == id.generation && SLOTS[slot].is_some();
+    if pending {
+        let i = HEAP[..HEAP_LEN].iter().position(|&s| s as usize == slot).unwrap();
+        heap_remove(i);
+        SLOTS[slot] = None;
+        alarm_program();
+    }
+    alarm_unlock();
+    pending
+}
+
+/// Returns the number of pending alarms.
+pub unsafe fn alarm_count() -> usize {
+    HEAP_LEN
+}
+
+/// Runs every alarm that is due and reprograms the compare channel. Called
+/// from `interrupt_vector`.
+#[allow(static_mut_refs)]
+pub unsafe fn alarm_irq_handler() {
+    let Some(channel) = CHANNEL else {
+        return;
+    };
+    if !timer_compare_pending(channel) {
+        return;
+    }
+    timer_compare_clear(channel);
+    let now = Instant::now();
+    while HEAP_LEN > 0 && heap_deadline(0) <= now {
+        let slot = HEAP[0] as usize;
+        let alarm = SLOTS[slot].unwrap();
+        match alarm.period {
+            Some(period) => {
+                // saturating: an alarm pushed past the end of time never fires
+                let mut next = alarm.deadline.saturating_add(period);
+                if next <= now {
+                    next = now.saturating_add(period);
+                }
+                SLOTS[slot].as_mut().unwrap().deadline = next;
+                sift_down(0);
+            }
+            None => {
+                heap_remove(0);
+                SLOTS[slot] = None;
+            }
+        }
+        (alarm.handler)(alarm.arg);
+    }
+    alarm_program();
+}
+
+/// Masks the service's interrupt so threads can update the heap. (Threads
+/// run in user mode and cannot mask IRQs in the CPSR.)
+unsafe fn alarm_lock() {
+    if let Some(channel) = CHANNEL {
+        irq_disable(channel.irq());
+    }
+}
+
+unsafe fn alarm_unlock() {
+    if let Some(channel) = CHANNEL {
+        irq_enable(channel.irq());
+    }
+}
+
+/// Stores `alarm` in a free slot and queues it.
+#[allow(static_mut_refs)]
+unsafe fn alarm_add(alarm: Alarm) -> Result<AlarmId, AlarmError> {
+    alarm_lock();
+    let Some(slot) = SLOTS.iter().position(|s| s.is_none()) else {
+        alarm_unlock();
+        return Err(AlarmError::Full);
+    };
+    SLOTS[slot] = Some(alarm);
+    GENERATIONS[slot] = GENERATIONS[slot].wrapping_add(1);
+    HEAP[HEAP_LEN] = slot as u8;
+    HEAP_LEN += 1;
+    sift_up(HEAP_LEN - 1);
+    alarm_program();
+    alarm_unlock();
+    Ok(AlarmId { slot: slot as u8, generation: GENERATIONS[slot] })
+}
+
+/// Programs the compare channel for the earliest deadline, or a moment from
+/// now if it is already due.
+#[allow(static_mut_refs)]
+unsafe fn alarm_program() {
+    let (Some(channel), true) = (CHANNEL, HEAP_LEN > 0) else {
+        return;
+    };
+    let deadline = heap_deadline(0).as_micros();
+    loop {
+        let now = timer_get_usec64();
+        let target = deadline.clamp(now + MIN_LEAD_US, now + MAX_STEP_US);
+        timer_compare_set(channel, target as u32);
+        if timer_get_usec64() < target {
+            break;
+        }
+    }
+}
+
+/// Returns the deadline of the alarm at heap position `i`.
+#[allow(static_mut_refs)]
+unsafe fn heap_deadline(i: usize) -> Instant {
+    SLOTS[HEAP[i] as usize].unwrap().deadline
+}
+
+#[allow(static_mut_refs)]
+unsafe fn sift_up(mut i: usize) {
+    while i > 0 {
+        let parent = (i - 1) / 2;
+        if heap_deadline(parent) <= heap_deadline(i) {
+            break;
+        }
+        HEAP.swap(parent, i);
+        i = parent;
+    }
+}
+
+#[allow(static_mut_refs)]
+unsafe fn sift_down(mut i: usize) {
+    loop {
+        let mut min = i;
+        for child in [2 * i + 1, 2 * i + 2] {
+            if child < HEAP_LEN && heap_deadline(child) < heap_deadline(min) {
+                min = child;
+            }
+        }
+        if min == i {
+            break;
+        }
+        HEAP.swap(min, i);
+        i = min;
+    }
+}
+
+/// Removes the entry at heap position `i`.
+#[allow(static_mut_refs)]
+unsafe fn heap_remove(i: usize) {
+    HEAP_LEN -= 1;
+    if i < HEAP_LEN {
+        HEAP[i] = HEAP[HEAP_LEN];
+        sift_down(i);
+        sift_up(i);
+    }
+}
@@ -0,0 +1,87 @@
+//! Periodic interrupts from the ARM (SP804-style) timer. Unlike the system
+//! timer it reloads itself, so a fixed-rate tick costs no reprogramming in the
+//! handler. It is clocked from the core clock, so the period drifts if the
+//! firmware changes that clock (e.g. with dynamic frequency scaling).
+
+use crate::io::*;
+use crate::mailbox::*;
+use crate::interrupts::*;
+
+const ARM_TIMER_BASE: u32 = 0x2000B400;
+const ARM_TIMER_LOAD: u32 = ARM_TIMER_BASE;
+const ARM_TIMER_VALUE: u32 = ARM_TIMER_BASE + 0x04;
+const ARM_TIMER_CONTROL: u32 = ARM_TIMER_BASE + 0x08;
+const ARM_TIMER_IRQCLR: u32 = ARM_TIMER_BASE + 0x0C;
+const ARM_TIMER_RAWIRQ: u32 = ARM_TIMER_BASE + 0x10;
+const ARM_TIMER_MSKIRQ: u32 = ARM_TIMER_BASE + 0x14;
+const ARM_TIMER_RELOAD: u32 = ARM_TIMER_BASE + 0x18;
+const ARM_TIMER_PREDIV: u32 = ARM_TIMER_BASE + 0x1C;
+
+// ARM_TIMER_CONTROL bits
+const CTL_32BIT: u8 = 1;
+const CTL_IRQ_ENABLE: u8 = 5;
+const CTL_ENABLE: u8 = 7;
+
+/// Largest pre-divider (the register is 10 bits wide).
+const PREDIV_MAX: u32 = 0x3FF;
+
+/// Rate the counter is pre-divided to: one tick per microsecond.
+const TICK_HZ: u32 = 1_000_000;
+
+/// Callback run on every timer interrupt.
+pub type ArmTimerHandler = unsafe fn();
+
+static mut ARM_TIMER_HANDLER: Option<ArmTimerHandler> = None;
+
+/// Starts the ARM timer with an interrupt every `period_us` microseconds that
+/// runs `handler`. Replaces any previous handler.
+/// Precondition: `period_us > 0`; interrupts are set up with `interrupt_init()`.
+pub unsafe fn arm_timer_start(period_us: u32, handler: ArmTimerHandler) {
+    assert!(period_us > 0);
+    arm_timer_stop();
+    ARM_TIMER_HANDLER = Some(handler);
+
+    let prediv = (mbox_core_clock() / TICK_HZ).clamp(1, PREDIV_MAX + 1) - 1;
+    dsb();
+    put32(ARM_TIMER_PREDIV, prediv);
+    put32(ARM_TIMER_LOAD, period_us - 1);
+    put32(ARM_TIMER_RELOAD, period_us - 1);
+    put32(ARM_TIMER_IRQCLR, 1);
+    put32(ARM_TIMER_CONTROL, 1 << CTL_32BIT | 1 << CTL_IRQ_ENABLE | 1 << CTL_ENABLE);
+    dsb();
+    arm_timer_irq_enable();
+}
+
+/// Stops the ARM timer and its interrupt.
+pub unsafe fn arm_timer_stop() {
+    arm_timer_irq_disable();
+    dsb();
+    put32(ARM_TIMER_CONTROL, 1 << CTL_32BIT);
+    put32(ARM_TIMER_IRQCLR, 1);
+    dsb();
+    ARM_TIMER_HANDLER = None;
+}
+
+/// Returns the remaining microseconds until the next interrupt.
+pub unsafe fn arm_timer_value() -> u32 {
+    get32(ARM_TIMER_VALUE)
+}
+
+/// Returns whether the timer has an unacknowledged interrupt, routed or not.
+pub unsafe fn arm_timer_raw_pending() -> bool {
+    get32(ARM_TIMER_RAWIRQ) & 1 == 1
+}
+
+/// Acknowledges the pending timer interrupt and runs the handler. Called from
+/// `interrupt_vector`.
+pub unsafe fn arm_timer_irq_handler() {
+    if get32(ARM_TIMER_MSKIRQ) & 1 == 0 {
+        return;
+    }
+    dsb();
+    put32(ARM_TIMER_IRQCLR, 1);
+    dsb();
+    if let Some(handler) = ARM_TIMER_HANDLER {
+        handler();
+    }
+}
@@ -0,0 +1,212 @@
+//! Parsing of the boot parameters the firmware passes in r0-r2: either an
+//! ATAG list or a flattened device tree (FDT).
+
+use crate::io::*;
+use crate::memmap::Region;
+
+const ATAG_NONE: u32 = 0x00000000;
+const ATAG_CORE: u32 = 0x54410001;
+const ATAG_MEM: u32 = 0x54410002;
+const ATAG_INITRD2: u32 = 0x54420005;
+const ATAG_CMDLINE: u32 = 0x54410009;
+
+/// Where the firmware places ATAGs if r2 does not point at a valid list.
+const ATAG_DEFAULT_ADDR: u32 = 0x100;
+const ATAG_MAX_TAGS: usize = 64;
+
+const FDT_MAGIC: u32 = 0xD00DFEED;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Where the boot parameters came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootSource {
+    Atags,
+    DeviceTree,
+    /// Neither a valid ATAG list nor a device tree was found.
+    None,
+}
+
+/// Boot parameters handed over by the firmware.
+pub struct BootInfo {
+    /// Machine type passed in r1.
+    pub machine_type: u32,
+    pub source: BootSource,
+    /// ARM memory as described by the firmware.
+    pub mem: Option<Region>,
+    /// Kernel command line (`cmdline.txt` plus any firmware additions).
+    pub cmdline: &'static str,
+    pub initrd: Option<Region>,
+}
+
+impl BootInfo {
+    /// Parses the registers `_start` received from the firmware: `r1` is the
+    /// machine type and `r2` points at an ATAG list or a device tree.
+    pub unsafe fn parse(_r0: u32, r1: u32, r2: u32) -> Self {
+        let mut info = Self {
+            machine_type: r1,
+            source: BootSource::None,
+            mem: None,
+            cmdline: "",
+            initrd: None,
+        };
+        if r2 != 0 && be32(r2) == FDT_MAGIC {
+            info.parse_fdt(r2);
+        } else if is_atag_list(r2) {
+            info.parse_atags(r2);
+        } else if is_atag_list(ATAG_DEFAULT_ADDR) {
+            info.parse_atags(ATAG_DEFAULT_ADDR);
+        }
+        info
+    }
+
+    /// Returns the value of the last `key=value` entry in the command line.
+    pub fn param(&self, key: &str) -> Option<&'static str> {
+        self.cmdline
+            .split_ascii_whitespace()
+            .rev()
+            .filter_map(|arg| arg.split_once('='))
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the value of `key` parsed as a decimal or `0x` hex number.
+    pub fn param_u32(&self, key: &str) -> Option<u32> {
+        let v = self.param(key)?;
+        match v.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => v.parse().ok(),
+        }
+    }
+
+    /// Fills in fields from the ATAG list at `addr`.
+    unsafe fn parse_atags(&mut self, mut addr: u32) {
+        self.source = BootSource::Atags;
+        for _ in 0..ATAG_MAX_TAGS {
+            let size = get32(addr);
+            let tag = get32(addr + 4);
+            if tag == ATAG_NONE || size < 2 {
+                break;
+            }
+            let data = addr + 8;
+            match tag {
+                ATAG_MEM => {
+                    let (len, start) = (get32(data), get32(data + 4));
+                    if let Some(end) = start.checked_add(len) {
+                        self.mem = Some(Region { start, end });
+                    }
+                }
+                ATAG_INITRD2 => {
+                    let (start, len) = (get32(data), get32(data + 4));
+                    if let Some(end) = start.checked_add(len) {
+                        self.initrd = Some(Region { start, end });
+                    }
+                }
+                ATAG_CMDLINE => self.cmdline = c_str(data, (size - 2) * 4),
+                _ => {}
+            }
+            addr += size * 4;
+        }
+    }
+
+    /// Fills in fields from the `/memory` and `/chosen` nodes of the device
+    /// tree at `base`.
+    unsafe fn parse_fdt(&mut self, base: u32) {
+        self.source = BootSource::DeviceTree;
+        let end = base + be32(base + 4);
+        let strings = base + be32(base + 12);
+        let mut p = base + be32(base + 8);
+
+        let mut addr_cells = 2;
+        let mut size_cells = 1;
+        let mut depth = 0;
+        let mut node = "";
+        let mut initrd_start = None;
+        let mut initrd_end = None;
+
+        while p < end {
+            let token = be32(p);
+            p += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(p, end - p);
+                    p += align4(name.len() as u32 + 1);
+                    depth += 1;
+                    if depth == 2 {
+                        node = name.split('@').next().unwrap_or("");
+                    }
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if depth < 2 {
+                        node = "";
+                    }
+                }
+                FDT_PROP => {
+                    let len = be32(p);
+                    let name_addr = strings + be32(p + 4);
+                    let name = c_str(name_addr, end - name_addr);
+                    let value = p + 8;
+                    p = value + align4(len);
+                    match (depth, node, name) {
+                        (1, _, "#address-cells") => addr_cells = be32(value),
+                        (1, _, "#size-cells") => size_cells = be32(value),
+                        (2, "memory", "reg") => {
+                            let start = cells(value, addr_cells);
+                            let size = cells(value + 4 * addr_cells, size_cells);
+                            if let Some(end) = start.checked_add(size) {
+                                self.mem = Some(Region { start, end });
+                            }
+                        }
+                        (2, "chosen", "bootargs") => self.cmdline = c_str(value, len),
+                        (2, "chosen", "linux,initrd-start") => {
+                            initrd_start = Some(cells(value, len / 4));
+                        }
+                        (2, "chosen", "linux,initrd-end") => {
+                            initrd_end = Some(cells(value, len / 4));
+                        }
+                        _ => {}
+                    }
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                _ => break, // malformed tree
+            }
+        }
+
+        if let (Some(start), Some(end)) = (initrd_start, initrd_end) {
+            self.initrd = Some(Region { start, end });
+        }
+    }
+}
+
+/// Returns whether `addr` holds an ATAG list (starts with `ATAG_CORE`).
+unsafe fn is_atag_list(addr: u32) -> bool {
+    addr != 0 && addr & 3 == 0 && get32(addr + 4) == ATAG_CORE
+}
+
+/// Reads a big-endian word, as stored in a device tree.
+unsafe fn be32(addr: u32) -> u32 {
+    u32::from_be(get32(addr))
+}
+
+/// Reads an `n`-cell big-endian number, keeping the low 32 bits.
+unsafe fn cells(addr: u32, n: u32) -> u32 {
+    if n == 0 { 0 } else { be32(addr + 4 * (n - 1)) }
+}
+
+/// Returns `n` rounded up to a multiple of 4.
+fn align4(n: u32) -> u32 {
+    (n + 3) & !3
+}
+
+/// Returns the NUL-terminated string at `addr`, reading at most `max` bytes.
+/// Invalid UTF-8 yields an empty string.
+unsafe fn c_str(addr: u32, max: u32) -> &'static str {
+    let bytes = core::slice::from_raw_parts(addr as *const u8, max as usize);
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
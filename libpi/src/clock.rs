@@ -0,0 +1,263 @@
+//! Clock manager support for the peripheral clocks: the general-purpose
+//! clocks GPCLK0-2, which can be output on GPIO 4/5/6 (e.g. as a reference
+//! clock for an external chip), and the PWM clock.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::gpio::*;
+use crate::pin::*;
+
+const CM_BASE: u32 = 0x20101000;
+const CM_GP0CTL: u32 = CM_BASE + 0x70;
+const CM_GP1CTL: u32 = CM_BASE + 0x78;
+const CM_GP2CTL: u32 = CM_BASE + 0x80;
+const CM_PWMCTL: u32 = CM_BASE + 0xA0;
+
+/// Every clock manager write must carry this password in bits 31-24.
+const CM_PASSWORD: u32 = 0x5A000000;
+
+const CM_ENAB: u8 = 4;
+const CM_KILL: u8 = 5;
+const CM_BUSY: u8 = 7;
+const CM_MASH: u8 = 9;
+
+/// Largest integer divisor (DIVI is 12 bits).
+const CM_DIVI_MAX: u32 = 0xFFF;
+/// Largest fractional divisor, in 1/4096ths (DIVF is 12 bits).
+const CM_DIVF_MAX: u32 = 0xFFF;
+
+/// Clock sources a peripheral clock can be derived from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    Gnd = 0,
+    /// 19.2 MHz crystal oscillator
+    Oscillator = 1,
+    TestDebug0 = 2,
+    TestDebug1 = 3,
+    PllA = 4,
+    PllC = 5,
+    /// 500 MHz
+    PllD = 6,
+    HdmiAux = 7,
+}
+
+impl ClockSource {
+    /// Returns the source frequency in Hz if it is fixed.
+    pub fn hz(self) -> Option<u32> {
+        match self {
+            ClockSource::Oscillator => Some(19_200_000),
+            ClockSource::PllD => Some(500_000_000),
+            _ => None,
+        }
+    }
+}
+
+/// Peripheral clocks driven by the clock manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    /// GPCLK0, output on GPIO 4
+    Gp0,
+    /// GPCLK1, output on GPIO 5
+    Gp1,
+    /// GPCLK2, output on GPIO 6
+    Gp2,
+    /// Clock of the PWM peripheral
+    Pwm,
+}
+
+impl ClockId {
+    /// Returns the control register; the divisor register follows it.
+    fn ctl(self) -> u32 {
+        match self {
+            ClockId::Gp0 => CM_GP0CTL,
+            ClockId::Gp1 => CM_GP1CTL,
+            ClockId::Gp2 => CM_GP2CTL,
+            ClockId::Pwm => CM_PWMCTL,
+        }
+    }
+}
+
+/// MASH noise shaping of the fractional divisor. Higher stages push the
+/// jitter to higher frequencies but swing the output over a wider range of
+/// divisors (up to DIVI-3..DIVI+4 for `Stage3`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mash {
+    /// Integer division; DIVF is ignored.
+    Integer = 0,
+    Stage1 = 1,
+    Stage2 = 2,
+    Stage3 = 3,
+}
+
+impl Mash {
+    /// Returns the smallest DIVI the stage supports.
+    fn min_divi(self) -> u32 {
+        match self {
+            Mash::Integer => 1,
+            Mash::Stage1 => 2,
+            Mash::Stage2 => 3,
+            Mash::Stage3 => 5,
+        }
+    }
+}
+
+/// Errors reported when configuring a clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    /// The requested rate needs a divisor outside 1-4095.
+    DivisorOutOfRange,
+    /// The source has no fixed frequency to divide.
+    UnknownSourceRate,
+    /// The pin cannot output a general-purpose clock.
+    NotClockPin(u8),
+    Gpio(GpioError),
+}
+
+impl From<GpioError> for ClockError {
+    fn from(e: GpioError) -> Self {
+        ClockError::Gpio(e)
+    }
+}
+
+/// Stops clock `id` and waits until it is no longer busy.
+pub unsafe fn clock_stop(id: ClockId) {
+    let ctl = id.ctl();
+    dsb();
+    put32(ctl, CM_PASSWORD | bit_clr(get32(ctl) & 0xFFFFFF, CM_ENAB));
+    while bit_is_set(get32(ctl), CM_BUSY) {}
+    dsb();
+}
+
+/// Stops clock `id` immediately, without waiting for a clean cycle end.
+pub unsafe fn clock_kill(id: ClockId) {
+    dsb();
+    put32(id.ctl(), CM_PASSWORD | bit_set(0, CM_KILL));
+    while bit_is_set(get32(id.ctl()), CM_BUSY) {}
+    dsb();
+}
+
+/// Runs clock `id` from `source` divided by `divi`.
+pub unsafe fn clock_start(
+    id: ClockId,
+    source: ClockSource,
+    divi: u32
+) -> Result<(), ClockError> {
+    clock_start_frac(id, source, divi, 0, Mash::Integer)
+}
+
+/// Runs clock `id` from `source` divided by `divi + divf / 4096`, with the
+/// fraction applied by `mash`.
+pub unsafe fn clock_start_frac(
+    id: ClockId,
+    source: ClockSource,
+    divi: u32,
+    divf: u32,
+    mash: Mash
+) -> Result<(), ClockError> {
+    if divi < mash.min_divi() || divi > CM_DIVI_MAX || divf > CM_DIVF_MAX {
+        return Err(ClockError::DivisorOutOfRange);
+    }
+    // source, MASH and divisor may only change while the clock is stopped
+    clock_stop(id);
+    let ctl = id.ctl();
+    let cfg = CM_PASSWORD | (mash as u32) << CM_MASH | source as u32;
+    dsb();
+    put32(ctl + 4, CM_PASSWORD | divi << 12 | divf);
+    put32(ctl, cfg);
+    put32(ctl, cfg | 1 << CM_ENAB);
+    dsb();
+    Ok(())
+}
+
+/// Runs clock `id` from `source` as close to `hz` as an integer divisor
+/// allows, and returns the actual frequency.
+pub unsafe fn clock_set_hz(
+    id: ClockId,
+    source: ClockSource,
+    hz: u32
+) -> Result<u32, ClockError> {
+    let src = source.hz().ok_or(ClockError::UnknownSourceRate)?;
+    if hz == 0 {
+        return Err(ClockError::DivisorOutOfRange);
+    }
+    let divi = (src + hz / 2) / hz;
+    clock_start(id, source, divi)?;
+    Ok(src / divi)
+}
+
+/// Runs clock `id` from `source` at `hz` on average using a fractional
+/// divisor, and returns the average frequency actually set. With
+/// `Mash::Integer` this is the same as `clock_set_hz()`.
+pub unsafe fn clock_set_hz_frac(
+    id: ClockId,
+    source: ClockSource,
+    hz: u32,
+    mash: Mash
+) -> Result<u32, ClockError> {
+    if mash == Mash::Integer {
+        return clock_set_hz(id, source, hz);
+    }
+    let src = source.hz().ok_or(ClockError::UnknownSourceRate)? as u64;
+    if hz == 0 {
+        return Err(ClockError::DivisorOutOfRange);
+    }
+    // divisor in 1/4096ths, rounded to nearest
+    let div = ((src << 12) + hz as u64 / 2) / hz as u64;
+    if div >> 12 > CM_DIVI_MAX as u64 {
+        return Err(ClockError::DivisorOutOfRange);
+    }
+    clock_start_frac(id, source, (div >> 12) as u32, (div & 0xFFF) as u32, mash)?;
+    Ok(((src << 12) / div) as u32)
+}
+
+/// Returns the general-purpose clock that `pin` can output.
+fn gpclk_pin(pin: u8) -> Result<ClockId, ClockError> {
+    match pin {
+        4 => Ok(ClockId::Gp0),
+        5 => Ok(ClockId::Gp1),
+        6 => Ok(ClockId::Gp2),
+        _ => Err(ClockError::NotClockPin(pin)),
+    }
+}
+
+/// Pins reserved by `gpclk_init()` (bit `i` = pin `i`).
+static mut GPCLK_PINS: u64 = 0;
+
+/// Outputs a clock of about `hz` from `source` on `pin` (GPIO 4, 5 or 6 for
+/// GPCLK0-2) and returns the average frequency actually set. Fails if `pin`
+/// is claimed or in use by another driver.
+pub unsafe fn gpclk_init(
+    pin: u8,
+    source: ClockSource,
+    hz: u32,
+    mash: Mash
+) -> Result<u32, ClockError> {
+    let id = gpclk_pin(pin)?;
+    let reserved = (GPCLK_PINS >> pin) & 1 == 1;
+    if !reserved {
+        gpio_reserve(pin)?;
+    }
+    let actual = match clock_set_hz_frac(id, source, hz, mash) {
+        Ok(actual) => actual,
+        Err(e) => {
+            if !reserved {
+                gpio_unreserve(pin);
+            }
+            return Err(e);
+        }
+    };
+    GPCLK_PINS |= 1 << pin;
+    gpio_set_fn(pin, 0b100).unwrap(); // ALT0
+    Ok(actual)
+}
+
+/// Stops the clock output on `pin` and makes it an input again.
+pub unsafe fn gpclk_disable(pin: u8) -> Result<(), ClockError> {
+    clock_stop(gpclk_pin(pin)?);
+    if (GPCLK_PINS >> pin) & 1 == 1 {
+        GPCLK_PINS &= !(1 << pin);
+        gpio_set_input(pin).unwrap();
+        gpio_unreserve(pin);
+    }
+    Ok(())
+}
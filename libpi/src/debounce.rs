@@ -0,0 +1,234 @@
+//! Software debouncing for mechanical buttons. GPIO edges only mark a button
+//! as active; `debounce_tick()` then samples it until its level has been
+//! stable for the configured window and turns the result into clean events.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::gpio::*;
+use crate::pin::*;
+use crate::interrupts::*;
+use crate::time::*;
+
+const MAX_BUTTONS: usize = 8;
+const EVENT_QUEUE_LEN: usize = 16;
+
+/// Debounced button events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Press,
+    Release,
+    /// Held for at least `long_press_ms`; sent once per press.
+    LongPress,
+    /// Pressed again within `double_click_ms` of the previous release; sent
+    /// after the second `Press`.
+    DoubleClick,
+}
+
+/// Callback for debounced events; receives the pin and the event.
+pub type ButtonHandler = unsafe fn(u8, ButtonEvent);
+
+/// Debounce timing and polarity of one button.
+#[derive(Clone, Copy, Debug)]
+pub struct DebounceConfig {
+    /// How long the level must stay unchanged before it is accepted.
+    pub stable_ms: u32,
+    pub long_press_ms: u32,
+    pub double_click_ms: u32,
+    /// `true` if the pin reads high while pressed (button to 3.3V with a
+    /// pull-down), `false` for a button to ground with a pull-up.
+    pub active_high: bool,
+}
+
+impl DebounceConfig {
+    pub const DEFAULT: Self = Self {
+        stable_ms: 20,
+        long_press_ms: 1000,
+        double_click_ms: 400,
+        active_high: true,
+    };
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// State of one registered button. Times are system timer microseconds.
+struct Button {
+    pin: u8,
+    active_high: bool,
+    /// `DebounceConfig` times in microseconds, saturated.
+    stable_us: u32,
+    long_press_us: u32,
+    double_click_us: u32,
+    handler: Option<ButtonHandler>,
+    /// Set for an edge, cleared once there is nothing left to time. Only
+    /// `debounce_tick()` writes it.
+    active: bool,
+    pressed: bool,
+    raw: bool,
+    raw_since: u32,
+    pressed_at: u32,
+    released_at: Option<u32>,
+    long_sent: bool,
+}
+
+static mut BUTTONS: [Option<Button>; MAX_BUTTONS] = [const { None }; MAX_BUTTONS];
+
+/// Buttons with an edge not yet seen by `debounce_tick()` (bit `i` = slot
+/// `i`). Set by the edge interrupt and taken by the tick, so an edge arriving
+/// while a button is sampled is not lost.
+static EDGES_PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// Events of buttons registered without a handler.
+static mut EVENTS: [(u8, ButtonEvent); EVENT_QUEUE_LEN] =
+    [(0, ButtonEvent::Press); EVENT_QUEUE_LEN];
+static mut EVENTS_HEAD: usize = 0;
+static mut EVENTS_LEN: usize = 0;
+
+/// Registers a debounced button on `pin`: configures it as an input with the
+/// matching pull resistor and enables rising and falling edge interrupts.
+/// Events go to `handler`, or to the queue read by `button_event_poll()` if
+/// `handler` is `None`. Returns `None` if all button slots are used, or an
+/// error if `pin` is already registered, claimed or in use by a driver.
+#[allow(static_mut_refs)]
+pub unsafe fn button_register(
+    pin: u8,
+    cfg: DebounceConfig,
+    handler: Option<ButtonHandler>
+) -> Result<Option<usize>, GpioError> {
+    let Some(i) = BUTTONS.iter().position(|b| b.is_none()) else {
+        return Ok(None);
+    };
+    gpio_reserve(pin)?;
+    let pull = if cfg.active_high { Pull::Down } else { Pull::Up };
+    gpio_set_input(pin)?;
+    gpio_set_pull(pin, pull)?;
+    let raw = gpio_read(pin)? == cfg.active_high as u8;
+    BUTTONS[i] = Some(Button {
+        pin,
+        active_high: cfg.active_high,
+        stable_us: cfg.stable_ms.saturating_mul(1000),
+        long_press_us: cfg.long_press_ms.saturating_mul(1000),
+        double_click_us: cfg.double_click_ms.saturating_mul(1000),
+        handler,
+        active: false,
+        pressed: raw,
+        raw,
+        raw_since: timer_get_usec(),
+        pressed_at: 0,
+        released_at: None,
+        long_sent: raw,
+    });
+    gpio_on_event(pin, EdgeKind::Rising, button_edge)?;
+    gpio_on_event(pin, EdgeKind::Falling, button_edge)?;
+    Ok(Some(i))
+}
+
+/// GPIO event callback: marks the button on `pin` for sampling.
+#[allow(static_mut_refs)]
+unsafe fn button_edge(pin: u8) {
+    for (i, button) in BUTTONS.iter().enumerate() {
+        if button.as_ref().is_some_and(|b| b.pin == pin) {
+            EDGES_PENDING.fetch_or(1 << i, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Samples every active button once and emits the events that are due.
+#[allow(static_mut_refs)]
+pub unsafe fn debounce_tick() {
+    let now = timer_get_usec();
+    let edges = EDGES_PENDING.swap(0, Ordering::AcqRel);
+    for (i, button) in BUTTONS.iter_mut().enumerate() {
+        let Some(button) = button else {
+            continue;
+        };
+        button.active |= edges & (1 << i) != 0;
+        if button.active {
+            button.sample(now);
+        }
+    }
+}
+
+/// Thread body that calls `debounce_tick()` every `tick_ms` milliseconds,
+/// e.g. `SCHEDULER.fork(debounce_thread, 5)`.
+pub unsafe extern "C" fn debounce_thread(tick_ms: u32) {
+    loop {
+        debounce_tick();
+        Timer::set(tick_ms).wait_and_yield();
+    }
+}
+
+/// Returns the oldest queued event as `(pin, event)`, if any.
+pub unsafe fn button_event_poll() -> Option<(u8, ButtonEvent)> {
+    if EVENTS_LEN == 0 {
+        return None;
+    }
+    let event = EVENTS[EVENTS_HEAD];
+    EVENTS_HEAD = (EVENTS_HEAD + 1) % EVENT_QUEUE_LEN;
+    EVENTS_LEN -= 1;
+    Some(event)
+}
+
+/// Queues `event`, dropping the oldest one if the queue is full.
+unsafe fn button_event_push(event: (u8, ButtonEvent)) {
+    if EVENTS_LEN == EVENT_QUEUE_LEN {
+        button_event_poll();
+    }
+    EVENTS[(EVENTS_HEAD + EVENTS_LEN) % EVENT_QUEUE_LEN] = event;
+    EVENTS_LEN += 1;
+}
+
+impl Button {
+    /// Runs the debounce state machine at time `now`.
+    unsafe fn sample(&mut self, now: u32) {
+        let raw = gpio_read(self.pin) == Ok(self.active_high as u8);
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+
+        let stable = now.wrapping_sub(self.raw_since) >= self.stable_us;
+        if stable && raw != self.pressed {
+            self.pressed = raw;
+            if raw {
+                self.pressed_at = now;
+                self.long_sent = false;
+                self.emit(ButtonEvent::Press);
+                if let Some(t) = self.released_at.take() {
+                    if now.wrapping_sub(t) <= self.double_click_us {
+                        self.emit(ButtonEvent::DoubleClick);
+                    }
+                }
+            } else {
+                self.released_at = Some(now);
+                self.emit(ButtonEvent::Release);
+            }
+        }
+
+        if self.pressed && !self.long_sent
+            && now.wrapping_sub(self.pressed_at) >= self.long_press_us {
+            self.long_sent = true;
+            self.emit(ButtonEvent::LongPress);
+        }
+
+        if let Some(t) = self.released_at {
+            if now.wrapping_sub(t) > self.double_click_us {
+                self.released_at = None;
+            }
+        }
+
+        // Nothing left to time: wait for the next edge.
+        self.active = !stable || raw != self.pressed
+            || (self.pressed && !self.long_sent) || self.released_at.is_some();
+    }
+
+    /// Delivers `event` to the handler or the event queue.
+    unsafe fn emit(&self, event: ButtonEvent) {
+        match self.handler {
+            Some(handler) => handler(self.pin, event),
+            None => button_event_push((self.pin, event)),
+        }
+    }
+}
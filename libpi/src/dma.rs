@@ -0,0 +1,325 @@
+//! DMA controller driver. A transfer is described by a chain of control
+//! blocks in memory; the engine follows the chain on its own and can be paced
+//! by a peripheral's DREQ line. The engine sees memory through the VideoCore
+//! bus, so every address in a control block is a bus address (see
+//! `dma_bus_addr()`). The data cache is off, so buffers need no cache
+//! maintenance.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::interrupts::*;
+
+const DMA_BASE: u32 = 0x20007000;
+const DMA_INT_STATUS: u32 = DMA_BASE + 0xFE0;
+const DMA_ENABLE: u32 = DMA_BASE + 0xFF0;
+
+// per-channel registers, relative to the channel base
+const DMA_CS: u32 = 0x00;
+const DMA_CONBLK_AD: u32 = 0x04;
+const DMA_DEBUG: u32 = 0x20;
+
+// DMA_CS bits
+const CS_ACTIVE: u8 = 0;
+const CS_END: u8 = 1;
+const CS_INT: u8 = 2;
+const CS_ERROR: u8 = 8;
+const CS_PRIORITY: u8 = 16;
+const CS_PANIC_PRIORITY: u8 = 20;
+const CS_WAIT_WRITES: u8 = 28;
+const CS_ABORT: u8 = 30;
+const CS_RESET: u8 = 31;
+
+// TI bits
+const TI_INTEN: u8 = 0;
+const TI_WAIT_RESP: u8 = 3;
+const TI_DEST_INC: u8 = 4;
+const TI_DEST_DREQ: u8 = 6;
+const TI_SRC_INC: u8 = 8;
+const TI_SRC_DREQ: u8 = 10;
+
+/// AXI priority of normal and panic transfers (0-15).
+const DMA_PRIORITY: u32 = 8;
+
+/// Channels the firmware leaves to the ARM (bit `i` = channel `i`).
+const DMA_CHANNELS_FREE: u16 = 0x7F35;
+const DMA_CHANNELS: usize = 15;
+
+/// Peripheral IRQ number of channel 0; channels up to 10 follow, and
+/// channels 11-14 share `DMA_SHARED_IRQ`.
+const DMA0_IRQ: u8 = 16;
+const DMA_SHARED_IRQ: u8 = 27;
+
+/// Largest transfer length of one control block.
+pub const DMA_MAX_LEN: u32 = 0x3FFFFFFF;
+
+const PERIPHERAL_BASE: u32 = 0x20000000;
+const PERIPHERAL_END: u32 = 0x21000000;
+const PERIPHERAL_BUS_BASE: u32 = 0x7E000000;
+
+/// Bus alias of RAM that bypasses the VideoCore L2 cache.
+const RAM_BUS_UNCACHED: u32 = 0xC0000000;
+
+/// Peripherals that can pace a transfer (DREQ lines).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dreq {
+    /// Always asserted: run at full speed.
+    None = 0,
+    Dsi = 1,
+    PcmTx = 2,
+    PcmRx = 3,
+    Smi = 4,
+    Pwm = 5,
+    SpiTx = 6,
+    SpiRx = 7,
+    BscSlaveTx = 8,
+    BscSlaveRx = 9,
+    Emmc = 11,
+    /// PL011 UART0 transmit
+    UartTx = 12,
+    SdHost = 13,
+    /// PL011 UART0 receive
+    UartRx = 14,
+    Hdmi = 17,
+}
+
+/// Errors reported by the DMA driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DmaError {
+    /// All channels available to the ARM are allocated.
+    NoFreeChannel,
+    /// The engine stopped on a bus error; holds the channel's DEBUG flags.
+    Bus(u32),
+}
+
+/// Callback run from the DMA interrupt; receives the channel number.
+pub type DmaHandler = unsafe fn(u8);
+
+/// A DMA control block. The engine reads it from memory, so it must stay in
+/// place and unchanged until the transfer using it has finished.
+#[repr(C, align(32))]
+#[derive(Clone, Copy, Debug)]
+pub struct ControlBlock {
+    ti: u32,
+    source_ad: u32,
+    dest_ad: u32,
+    txfr_len: u32,
+    stride: u32,
+    nextconbk: u32,
+    _reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    /// Returns a block that copies `len` bytes from `src` to `dst` (ARM
+    /// addresses), incrementing both.
+    pub fn copy(dst: u32, src: u32, len: u32) -> Self {
+        assert!(len <= DMA_MAX_LEN);
+        Self {
+            ti: 1 << TI_WAIT_RESP | 1 << TI_SRC_INC | 1 << TI_DEST_INC,
+            source_ad: dma_bus_addr(src),
+            dest_ad: dma_bus_addr(dst),
+            txfr_len: len,
+            stride: 0,
+            nextconbk: 0,
+            _reserved: [0; 2],
+        }
+    }
+
+    /// Returns a block that fills `len` bytes at `dst` with the 32-bit word at
+    /// `word`.
+    /// Precondition: `len` is a multiple of 4
+    pub fn fill(dst: u32, word: *const u32, len: u32) -> Self {
+        assert!(len % 4 == 0);
+        let mut cb = Self::copy(dst, word as u32, len);
+        cb.ti = bit_clr(cb.ti, TI_SRC_INC);
+        cb
+    }
+
+    /// Sets whether the source address increments after each word.
+    pub fn src_inc(mut self, inc: bool) -> Self {
+        self.ti = if inc { bit_set(self.ti, TI_SRC_INC) } else { bit_clr(self.ti, TI_SRC_INC) };
+        self
+    }
+
+    /// Sets whether the destination address increments after each word.
+    pub fn dest_inc(mut self, inc: bool) -> Self {
+        self.ti = if inc { bit_set(self.ti, TI_DEST_INC) } else { bit_clr(self.ti, TI_DEST_INC) };
+        self
+    }
+
+    /// Paces writes by `dreq`, e.g. `Dreq::SpiTx` when feeding a FIFO.
+    pub fn dest_dreq(mut self, dreq: Dreq) -> Self {
+        self.ti = bits_modify(bit_set(self.ti, TI_DEST_DREQ), 20, 16, dreq as u32);
+        self
+    }
+
+    /// Paces reads by `dreq`, e.g. `Dreq::SpiRx` when draining a FIFO.
+    pub fn src_dreq(mut self, dreq: Dreq) -> Self {
+        self.ti = bits_modify(bit_set(self.ti, TI_SRC_DREQ), 20, 16, dreq as u32);
+        self
+    }
+
+    /// Raises the channel interrupt once this block completes.
+    pub fn interrupt(mut self, enable: bool) -> Self {
+        self.ti = if enable { bit_set(self.ti, TI_INTEN) } else { bit_clr(self.ti, TI_INTEN) };
+        self
+    }
+
+    /// Continues with `next` once this block completes.
+    pub fn chain(&mut self, next: &ControlBlock) {
+        self.nextconbk = dma_bus_addr(next as *const ControlBlock as u32);
+    }
+
+    /// Ends the chain after this block.
+    pub fn unchain(&mut self) {
+        self.nextconbk = 0;
+    }
+}
+
+static mut ALLOCATED: u16 = 0;
+
+static mut DMA_HANDLERS: [Option<DmaHandler>; DMA_CHANNELS] = [None; DMA_CHANNELS];
+
+/// Returns the bus address the DMA engine uses for ARM physical address
+/// `addr`: peripherals move from 0x20xxxxxx to 0x7Exxxxxx, and RAM is accessed
+/// through the uncached 0xC0000000 alias so the ARM sees the data at once.
+pub fn dma_bus_addr(addr: u32) -> u32 {
+    if (PERIPHERAL_BASE..PERIPHERAL_END).contains(&addr) {
+        addr - PERIPHERAL_BASE + PERIPHERAL_BUS_BASE
+    } else {
+        (addr & 0x3FFFFFFF) | RAM_BUS_UNCACHED
+    }
+}
+
+/// Returns the ARM physical address of bus address `addr`.
+pub fn dma_arm_addr(addr: u32) -> u32 {
+    if (PERIPHERAL_BUS_BASE..PERIPHERAL_BUS_BASE + 0x1000000).contains(&addr) {
+        addr - PERIPHERAL_BUS_BASE + PERIPHERAL_BASE
+    } else {
+        addr & 0x3FFFFFFF
+    }
+}
+
+/// Returns the base of channel `ch`'s registers.
+fn dma_channel_base(ch: u8) -> u32 {
+    DMA_BASE + 0x100 * ch as u32
+}
+
+/// Returns the interrupt line of channel `ch`.
+fn dma_irq(ch: u8) -> u8 {
+    if ch <= 10 { DMA0_IRQ + ch } else { DMA_SHARED_IRQ }
+}
+
+/// Allocates a free channel, enables and resets it, and returns its number.
+pub unsafe fn dma_alloc() -> Result<u8, DmaError> {
+    let free = DMA_CHANNELS_FREE & !ALLOCATED;
+    if free == 0 {
+        return Err(DmaError::NoFreeChannel);
+    }
+    let ch = free.trailing_zeros() as u8;
+    ALLOCATED |= 1 << ch;
+    put32(DMA_ENABLE, bit_set(get32(DMA_ENABLE), ch));
+    put32(dma_channel_base(ch) + DMA_CS, 1 << CS_RESET);
+    dsb();
+    Ok(ch)
+}
+
+/// Aborts any transfer on `ch` and returns it to the pool.
+pub unsafe fn dma_free(ch: u8) {
+    dma_abort(ch);
+    DMA_HANDLERS[ch as usize] = None;
+    ALLOCATED &= !(1 << ch);
+}
+
+/// Starts channel `ch` on the chain beginning at `cb`.
+/// Precondition: `ch` was allocated and is idle; `cb`, the rest of its chain
+/// and their buffers stay valid until the transfer completes.
+pub unsafe fn dma_start(ch: u8, cb: &ControlBlock) {
+    assert!(ALLOCATED & (1 << ch) != 0);
+    let base = dma_channel_base(ch);
+    dsb();
+    put32(base + DMA_DEBUG, 0x7); // clear error flags
+    put32(base + DMA_CS, 1 << CS_END | 1 << CS_INT);
+    put32(base + DMA_CONBLK_AD, dma_bus_addr(cb as *const ControlBlock as u32));
+    put32(
+        base + DMA_CS,
+        1 << CS_ACTIVE | 1 << CS_WAIT_WRITES
+            | DMA_PRIORITY << CS_PRIORITY | DMA_PRIORITY << CS_PANIC_PRIORITY
+    );
+    dsb();
+}
+
+/// Returns whether channel `ch` is still transferring.
+pub unsafe fn dma_is_busy(ch: u8) -> bool {
+    bit_is_set(get32(dma_channel_base(ch) + DMA_CS), CS_ACTIVE)
+}
+
+/// Waits for channel `ch` to finish its chain.
+pub unsafe fn dma_wait(ch: u8) -> Result<(), DmaError> {
+    while dma_is_busy(ch) {
+        if let Err(e) = dma_error(ch) {
+            dma_abort(ch);
+            return Err(e);
+        }
+    }
+    dma_error(ch)
+}
+
+/// Returns the bus error reported by channel `ch`, if any.
+pub unsafe fn dma_error(ch: u8) -> Result<(), DmaError> {
+    let base = dma_channel_base(ch);
+    if bit_is_set(get32(base + DMA_CS), CS_ERROR) {
+        Err(DmaError::Bus(get32(base + DMA_DEBUG)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Stops channel `ch` and resets it.
+pub unsafe fn dma_abort(ch: u8) {
+    let base = dma_channel_base(ch);
+    dsb();
+    put32(base + DMA_CONBLK_AD, 0);
+    put32(base + DMA_CS, 1 << CS_ABORT);
+    put32(base + DMA_CS, 1 << CS_RESET);
+    dsb();
+}
+
+/// Copies `len` bytes from `src` to `dst` on channel `ch` and waits for it.
+/// Convenience for one-off transfers; `ControlBlock` and `dma_start()` allow
+/// the CPU to do other work meanwhile.
+pub unsafe fn dma_memcpy(ch: u8, dst: *mut u8, src: *const u8, len: u32) -> Result<(), DmaError> {
+    let cb = ControlBlock::copy(dst as u32, src as u32, len);
+    dma_start(ch, &cb);
+    dma_wait(ch)
+}
+
+/// Runs `handler` from the interrupt when a control block on `ch` that has
+/// `ControlBlock::interrupt(true)` completes.
+/// Precondition: interrupts are set up with `interrupt_init()`.
+pub unsafe fn dma_on_complete(ch: u8, handler: DmaHandler) {
+    DMA_HANDLERS[ch as usize] = Some(handler);
+    irq_enable(dma_irq(ch));
+}
+
+/// Returns whether any DMA channel interrupt is pending.
+pub unsafe fn dma_irq_pending() -> bool {
+    (DMA0_IRQ..=DMA_SHARED_IRQ).any(|irq| irq_pending(irq))
+}
+
+/// Acknowledges every channel interrupt and runs its handler. Called from
+/// `interrupt_vector`.
+pub unsafe fn dma_irq_handler() {
+    let status = get32(DMA_INT_STATUS) & DMA_CHANNELS_FREE as u32;
+    for ch in 0..DMA_CHANNELS as u8 {
+        if (status >> ch) & 1 == 0 {
+            continue;
+        }
+        let cs = dma_channel_base(ch) + DMA_CS;
+        // INT is write-1-to-clear; writing back ACTIVE lets a chain carry on
+        put32(cs, bit_set(get32(cs), CS_INT));
+        dsb();
+        if let Some(handler) = DMA_HANDLERS[ch as usize] {
+            handler(ch);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Framebuffer allocated through the mailbox, with drawing primitives and a
+//! text console.
+
+use core::fmt;
+use crate::font::*;
+use crate::mailbox::*;
+
+/// Framebuffer addresses are returned as VideoCore bus addresses.
+const BUS_TO_ARM: u32 = 0x3FFFFFFF;
+
+const PIXEL_ORDER_RGB: u32 = 1;
+
+pub const BLACK: u32 = 0x000000;
+pub const WHITE: u32 = 0xFFFFFF;
+pub const GREEN: u32 = 0x00FF00;
+
+/// Console drawn on the framebuffer allocated by `console_init()`.
+static mut CONSOLE: Option<Console> = None;
+
+/// A linear framebuffer. Colours are `0xRRGGBB`.
+pub struct Framebuffer {
+    base: u32,
+    size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pitch: u32,
+    pub depth: u32,
+    bgr: bool,
+}
+
+impl Framebuffer {
+    /// Asks the firmware for a `width` x `height` framebuffer with `depth`
+    /// bits per pixel.
+    /// Precondition: `depth` is 16, 24 or 32.
+    pub unsafe fn alloc(width: u32, height: u32, depth: u32) -> Result<Self, MboxError> {
+        assert!(matches!(depth, 16 | 24 | 32), "Unsupported depth!");
+        let mut msg = PropertyMessage::new();
+        let phys = msg.push(Tag::SetPhysicalSize(width, height))?;
+        msg.push(Tag::SetVirtualSize(width, height))?;
+        msg.push(Tag::SetVirtualOffset(0, 0))?;
+        let bpp = msg.push(Tag::SetDepth(depth))?;
+        let order = msg.push(Tag::SetPixelOrder(PIXEL_ORDER_RGB))?;
+        let buffer = msg.push(Tag::AllocateBuffer(16))?;
+        let pitch = msg.push(Tag::Pitch)?;
+        msg.send()?;
+
+        let (width, height) = match msg.response(phys)? {
+            &[w, h] => (w, h),
+            _ => return Err(MboxError::TagNotHandled(TAG_SET_PHYSICAL_SIZE)),
+        };
+        let (base, size) = match msg.response(buffer)? {
+            &[base, size] if base != 0 => (base & BUS_TO_ARM, size),
+            _ => return Err(MboxError::TagNotHandled(TAG_ALLOCATE_BUFFER)),
+        };
+        Ok(Self {
+            base,
+            size,
+            width,
+            height,
+            pitch: msg.response(pitch)?.first().copied()
+                .ok_or(MboxError::TagNotHandled(TAG_GET_PITCH))?,
+            depth: msg.response(bpp)?.first().copied()
+                .ok_or(MboxError::TagNotHandled(TAG_SET_DEPTH))?,
+            bgr: msg.response(order)?.first().copied()
+                .ok_or(MboxError::TagNotHandled(TAG_SET_PIXEL_ORDER))? != PIXEL_ORDER_RGB,
+        })
+    }
+
+    /// Returns the size of the framebuffer in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns the address of pixel (`x`, `y`).
+    fn addr(&self, x: u32, y: u32) -> u32 {
+        self.base + y * self.pitch + x * (self.depth / 8)
+    }
+
+    /// Sets pixel (`x`, `y`) to `color`. Out-of-bounds pixels are ignored.
+    pub unsafe fn put_pixel(&mut self, x: u32, y: u32, color: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let color = if self.bgr { swap_rb(color) } else { color };
+        let addr = self.addr(x, y);
+        match self.depth {
+            16 => (addr as *mut u16).write_volatile(to_rgb565(color)),
+            24 => {
+                let ptr = addr as *mut u8;
+                ptr.write_volatile(color as u8);
+                ptr.add(1).write_volatile((color >> 8) as u8);
+                ptr.add(2).write_volatile((color >> 16) as u8);
+            }
+            _ => (addr as *mut u32).write_volatile(color),
+        }
+    }
+
+    /// Returns the colour of pixel (`x`, `y`).
+    /// Precondition: `x < self.width` and `y < self.height`
+    pub unsafe fn get_pixel(&self, x: u32, y: u32) -> u32 {
+        assert!(x < self.width && y < self.height);
+        let addr = self.addr(x, y);
+        let color = match self.depth {
+            16 => from_rgb565((addr as *const u16).read_volatile()),
+            24 => {
+                let ptr = addr as *const u8;
+                ptr.read_volatile() as u32
+                    | (ptr.add(1).read_volatile() as u32) << 8
+                    | (ptr.add(2).read_volatile() as u32) << 16
+            }
+            _ => (addr as *const u32).read_volatile() & 0xFFFFFF,
+        };
+        if self.bgr { swap_rb(color) } else { color }
+    }
+
+    /// Fills the `w` x `h` rectangle with top-left corner (`x`, `y`), clipped
+    /// to the screen.
+    pub unsafe fn fill_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: u32) {
+        for j in y..y.saturating_add(h).min(self.height) {
+            for i in x..x.saturating_add(w).min(self.width) {
+                self.put_pixel(i, j, color);
+            }
+        }
+    }
+
+    /// Fills the whole screen with `color`.
+    pub unsafe fn clear(&mut self, color: u32) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Copies the `w` x `h` row-major image `src` to (`x`, `y`), clipped to
+    /// the screen.
+    /// Precondition: `src.len() >= w * h`
+    pub unsafe fn blit(&mut self, x: u32, y: u32, w: u32, h: u32, src: &[u32]) {
+        let len = (w as usize).checked_mul(h as usize);
+        assert!(len.is_some_and(|len| src.len() >= len));
+        let visible_w = w.min(self.width.saturating_sub(x));
+        let visible_h = h.min(self.height.saturating_sub(y));
+        for j in 0..visible_h {
+            for i in 0..visible_w {
+                self.put_pixel(x + i, y + j, src[j as usize * w as usize + i as usize]);
+            }
+        }
+    }
+
+    /// Draws `c` with its top-left corner at (`x`, `y`).
+    pub unsafe fn draw_char(&mut self, x: u32, y: u32, c: u8, fg: u32, bg: u32) {
+        for (j, row) in glyph(c).iter().enumerate() {
+            for i in 0..FONT_WIDTH {
+                let color = if (row >> i) & 1 == 1 { fg } else { bg };
+                // off-screen pixels saturate and are then ignored
+                self.put_pixel(x.saturating_add(i), y.saturating_add(j as u32), color);
+            }
+        }
+    }
+
+    /// Moves the screen contents up by `rows` pixel rows and fills the exposed
+    /// rows at the bottom with `color`.
+    pub unsafe fn scroll_up(&mut self, rows: u32, color: u32) {
+        let rows = rows.min(self.height);
+        let bytes = ((self.height - rows) * self.pitch) as usize;
+        core::ptr::copy(
+            (self.base + rows * self.pitch) as *const u8,
+            self.base as *mut u8,
+            bytes
+        );
+        self.fill_rect(0, self.height - rows, self.width, rows, color);
+    }
+}
+
+/// Returns `color` with its red and blue channels exchanged.
+fn swap_rb(color: u32) -> u32 {
+    (color & 0x00FF00) | (color & 0xFF) << 16 | (color >> 16) & 0xFF
+}
+
+/// Converts `0xRRGGBB` to RGB565.
+fn to_rgb565(color: u32) -> u16 {
+    let (r, g, b) = ((color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF);
+    ((r >> 3) << 11 | (g >> 2) << 5 | b >> 3) as u16
+}
+
+/// Converts RGB565 to `0xRRGGBB`.
+fn from_rgb565(p: u16) -> u32 {
+    let p = p as u32;
+    let (r, g, b) = ((p >> 11) & 0x1F, (p >> 5) & 0x3F, p & 0x1F);
+    (r << 3) << 16 | (g << 2) << 8 | b << 3
+}
+
+/// A scrolling text console drawn with the built-in font.
+pub struct Console {
+    pub fb: Framebuffer,
+    col: u32,
+    row: u32,
+    pub fg: u32,
+    pub bg: u32,
+}
+
+impl Console {
+    /// Returns a console covering all of `fb`, cleared to `bg`.
+    pub unsafe fn new(mut fb: Framebuffer, fg: u32, bg: u32) -> Self {
+        fb.clear(bg);
+        Self { fb, col: 0, row: 0, fg, bg }
+    }
+
+    /// Returns the number of text columns.
+    pub fn cols(&self) -> u32 {
+        self.fb.width / FONT_WIDTH
+    }
+
+    /// Returns the number of text rows.
+    pub fn rows(&self) -> u32 {
+        self.fb.height / FONT_HEIGHT
+    }
+
+    /// Clears the screen and moves the cursor to the top-left corner.
+    pub unsafe fn clear(&mut self) {
+        self.fb.clear(self.bg);
+        self.col = 0;
+        self.row = 0;
+    }
+
+    /// Draws one byte at the cursor, handling `\n`, `\r`, `\t` and backspace,
+    /// and scrolls when the cursor moves past the last row.
+    pub unsafe fn put8(&mut self, c: u8) {
+        match c {
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            b'\t' => {
+                for _ in 0..4 - self.col % 4 {
+                    self.put8(b' ');
+                }
+            }
+            0x08 => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    let (x, y) = (self.col * FONT_WIDTH, self.row * FONT_HEIGHT);
+                    self.fb.draw_char(x, y, b' ', self.fg, self.bg);
+                }
+            }
+            _ => {
+                if self.col >= self.cols() {
+                    self.newline();
+                }
+                let (x, y) = (self.col * FONT_WIDTH, self.row * FONT_HEIGHT);
+                self.fb.draw_char(x, y, c, self.fg, self.bg);
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Writes a string to the console.
+    pub unsafe fn print(&mut self, msg: &str) {
+        for byte in msg.bytes() {
+            self.put8(byte);
+        }
+    }
+
+    /// Moves the cursor to the start of the next row, scrolling if needed.
+    unsafe fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+        } else {
+            self.fb.scroll_up(FONT_HEIGHT, self.bg);
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { self.print(s) };
+        Ok(())
+    }
+}
+
+/// Allocates a `width` x `height` framebuffer and makes it the global console
+/// used by `console_put8()` and `console_print()`.
+#[allow(static_mut_refs)]
+pub unsafe fn console_init(width: u32, height: u32) -> Result<(), MboxError> {
+    let fb = Framebuffer::alloc(width, height, 32)?;
+    CONSOLE = Some(Console::new(fb, GREEN, BLACK));
+    Ok(())
+}
+
+/// Returns the global console, if `console_init()` succeeded.
+#[allow(static_mut_refs)]
+pub unsafe fn console() -> Option<&'static mut Console> {
+    CONSOLE.as_mut()
+}
+
+/// Draws one byte on the global console. Does nothing without a console.
+pub unsafe fn console_put8(c: u8) {
+    if let Some(console) = console() {
+        console.put8(c);
+    }
+}
+
+/// Writes a string to the global console. Does nothing without a console.
+pub unsafe fn console_print(msg: &str) {
+    if let Some(console) = console() {
+        console.print(msg);
+    }
+}
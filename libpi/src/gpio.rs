@@ -2,61 +2,129 @@
 
 use crate::bits::*;
 use crate::io::*;
+use crate::time::*;
 
 const GPIO_BASE: u32 = 0x20200000;
 const GPIO_FSEL0: u32 = GPIO_BASE;
 const GPIO_SET0: u32 = GPIO_BASE + 0x1C;
-const GPIO_SET1: u32 = GPIO_BASE + 0x20; 
 const GPIO_CLR0: u32 = GPIO_BASE + 0x28;
-const GPIO_CLR1: u32 = GPIO_BASE + 0x2C;
 const GPIO_LEV0: u32 = GPIO_BASE + 0x34;
-const GPIO_LEV1: u32 = GPIO_BASE + 0x38;
+const GPIO_PUD: u32 = GPIO_BASE + 0x94;
+const GPIO_PUDCLK0: u32 = GPIO_BASE + 0x98;
+
+/// Number of GPIO pins on the BCM2835.
+pub const GPIO_PINS: u8 = 54;
+
+/// Cycles to wait for the pull-up/down control signal to set up and hold.
+const PUD_SETUP_CYCLES: u32 = 150;
+
+/// Internal pull resistor setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None = 0b00,
+    Down = 0b01,
+    Up = 0b10,
+}
+
+/// Errors reported by the GPIO driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpioError {
+    /// The BCM2835 has no such pin (valid pins are 0-53).
+    InvalidPin(u8),
+    /// Function select values are 3 bits.
+    InvalidFunction(u8),
+    /// A driver needs the pin, but it is claimed through `Gpio`.
+    Claimed(u8),
+    /// A driver needs the pin, but another driver has reserved it.
+    Reserved(u8),
+    /// The pin appears twice in a `PinGroup`.
+    DuplicatePin(u8),
+}
+
+/// Returns `Ok` if `pin` exists on the BCM2835.
+pub fn gpio_check_pin(pin: u8) -> Result<(), GpioError> {
+    if pin < GPIO_PINS { Ok(()) } else { Err(GpioError::InvalidPin(pin)) }
+}
+
+/// Returns the register holding `pin` given the bank 0 register `reg0` of a
+/// two-register bank (bank 1 follows at `reg0 + 4`), and the bit of `pin` in
+/// it.
+/// Precondition: `pin < 54`
+pub fn gpio_bank(reg0: u32, pin: u8) -> (u32, u8) {
+    match pin {
+        32.. => (reg0 + 4, pin - 32),
+        _    => (reg0, pin),
+    }
+}
 
 /// Sets `pin` to function via GPIO_FSEL to mode `y`
-/// Precondition: `y` is a 3-bit number
-pub unsafe fn gpio_set_fn(pin: u8, y: u8) {
-    if pin >= 32 && pin != 47 { return; }
+pub unsafe fn gpio_set_fn(pin: u8, y: u8) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    if y > 0b111 { return Err(GpioError::InvalidFunction(y)); }
     let addr = GPIO_FSEL0 + 4 * ((pin as u32) / 10);
     let shift = 3 * (pin % 10);
     let val = bits_modify(get32(addr), shift + 2, shift, y as u32);
     put32(addr, val);
+    Ok(())
 }
 
 /// Sets `pin` to be an output pin.
-pub unsafe fn gpio_set_output(pin: u8) {
-    gpio_set_fn(pin, 1);
+pub unsafe fn gpio_set_output(pin: u8) -> Result<(), GpioError> {
+    gpio_set_fn(pin, 1)
 }
 
 /// Sets `pin` to be an input pin.
-pub unsafe fn gpio_set_input(pin: u8) {
-    gpio_set_fn(pin, 0);
+pub unsafe fn gpio_set_input(pin: u8) -> Result<(), GpioError> {
+    gpio_set_fn(pin, 0)
+}
+
+/// Sets the internal pull resistor of `pin` to `pull` using the GPPUD /
+/// GPPUDCLK clocked sequence.
+pub unsafe fn gpio_set_pull(pin: u8, pull: Pull) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (clk, bit) = gpio_bank(GPIO_PUDCLK0, pin);
+    put32(GPIO_PUD, pull as u32);
+    delay_cycles(PUD_SETUP_CYCLES);
+    put32(clk, bit_set(0, bit));
+    delay_cycles(PUD_SETUP_CYCLES);
+    put32(GPIO_PUD, 0);
+    put32(clk, 0);
+    Ok(())
+}
+
+/// Sets `pin` to be an input pin with the internal pull-up enabled.
+pub unsafe fn gpio_set_input_pullup(pin: u8) -> Result<(), GpioError> {
+    gpio_set_input(pin)?;
+    gpio_set_pull(pin, Pull::Up)
+}
+
+/// Sets `pin` to be an input pin with the internal pull-down enabled.
+pub unsafe fn gpio_set_input_pulldown(pin: u8) -> Result<(), GpioError> {
+    gpio_set_input(pin)?;
+    gpio_set_pull(pin, Pull::Down)
 }
 
 /// Sets `pin` on.
 /// Precondition: `pin` is output
-pub unsafe fn gpio_set_on(pin: u8) {
-    if pin >= 32 && pin != 47 { return; }
-    let (addr, val) = match pin {
-        32.. => (GPIO_SET1, bit_set(0, pin - 32)),  
-        _    => (GPIO_SET0, bit_set(0, pin)),   
-    }; 
-    put32(addr, val);   
+pub unsafe fn gpio_set_on(pin: u8) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(GPIO_SET0, pin);
+    put32(addr, bit_set(0, bit));
+    Ok(())
 }
 
 /// Sets `pin` off.
 /// Precondition: `pin` is output
-pub unsafe fn gpio_set_off(pin: u8) {
-    if pin >= 32 && pin != 47 { return; }
-    let (addr, val) = match pin {
-        32.. => (GPIO_CLR1, bit_set(0, pin - 32)),  
-        _    => (GPIO_CLR0, bit_set(0, pin)),   
-    }; 
-    put32(addr, val);   
+pub unsafe fn gpio_set_off(pin: u8) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(GPIO_CLR0, pin);
+    put32(addr, bit_set(0, bit));
+    Ok(())
 }
 
 /// Sets `pin` to `b` 
 /// Precondition: `pin` is output and `b` is 0 or 1
-pub unsafe fn gpio_write(pin: u8, b: u8) {
+pub unsafe fn gpio_write(pin: u8, b: u8) -> Result<(), GpioError> {
     match b {
         1 => gpio_set_on(pin),
         0 => gpio_set_off(pin),
@@ -65,8 +133,8 @@ pub unsafe fn gpio_write(pin: u8, b: u8) {
 }
 
 /// Sets `pin` off if `pin` is on, and on otherwise.
-pub unsafe fn gpio_toggle(pin: u8) {
-    match gpio_read(pin) {
+pub unsafe fn gpio_toggle(pin: u8) -> Result<(), GpioError> {
+    match gpio_read(pin)? {
         1 => gpio_set_off(pin),
         0 => gpio_set_on(pin),
         _ => panic!("invalid bit!")
@@ -74,10 +142,106 @@ pub unsafe fn gpio_toggle(pin: u8) {
 }
 
 /// Returns the value of `pin`
-pub unsafe fn gpio_read(pin: u8) -> u8 {
-    if pin >= 32 && pin != 47 { panic!("Read from invalid pin!"); }
-    match pin {
-        32.. => bit_is_set(get32(GPIO_LEV1), pin - 32) as u8,
-        _    => bit_is_set(get32(GPIO_LEV0), pin) as u8,
+pub unsafe fn gpio_read(pin: u8) -> Result<u8, GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(GPIO_LEV0, pin);
+    Ok(bit_is_set(get32(addr), bit) as u8)
+}
+
+/// Mask of valid pins in a 64-bit pin mask.
+const GPIO_ALL_PINS: u64 = (1 << GPIO_PINS) - 1;
+
+/// Returns `Ok` if `mask` only names pins that exist.
+fn gpio_check_mask(mask: u64) -> Result<(), GpioError> {
+    match mask & !GPIO_ALL_PINS {
+        0 => Ok(()),
+        bad => Err(GpioError::InvalidPin(bad.trailing_zeros() as u8)),
     }
-}
\ No newline at end of file
+}
+
+/// Writes the 64-bit pin mask `mask` to the bank register pair at `reg0`,
+/// skipping the register of a bank with no bits set.
+unsafe fn gpio_put_mask(reg0: u32, mask: u64) {
+    if mask as u32 != 0 {
+        put32(reg0, mask as u32);
+    }
+    if (mask >> 32) as u32 != 0 {
+        put32(reg0 + 4, (mask >> 32) as u32);
+    }
+}
+
+/// Sets every pin in `set_mask` on and every pin in `clr_mask` off (bit `i` =
+/// pin `i`), using at most one write per GPSET/GPCLR register. Pins within a
+/// bank switch together; a pin in both masks ends up off.
+/// Precondition: the pins are outputs
+pub unsafe fn gpio_write_mask(set_mask: u64, clr_mask: u64) -> Result<(), GpioError> {
+    gpio_check_mask(set_mask | clr_mask)?;
+    gpio_put_mask(GPIO_SET0, set_mask);
+    gpio_put_mask(GPIO_CLR0, clr_mask);
+    Ok(())
+}
+
+/// Returns the level of every pin (bit `i` = pin `i`).
+pub unsafe fn gpio_read_all() -> u64 {
+    let lo = get32(GPIO_LEV0) as u64;
+    let hi = get32(GPIO_LEV0 + 4) as u64;
+    (hi << 32 | lo) & GPIO_ALL_PINS
+}
+
+/// An ordered list of pins driven as one `N`-bit value: bit `i` of the value
+/// goes to `pins[i]`.
+pub struct PinGroup<const N: usize> {
+    pins: [u8; N],
+    mask: u64,
+}
+
+impl<const N: usize> PinGroup<N> {
+    /// Returns a group of `pins`, least significant bit first. Fails if a pin
+    /// does not exist or appears twice. `N > 32` does not compile.
+    pub fn new(pins: [u8; N]) -> Result<Self, GpioError> {
+        const { assert!(N <= 32, "PinGroup holds at most 32 pins") };
+        let mut mask = 0u64;
+        for &pin in pins.iter() {
+            gpio_check_pin(pin)?;
+            if (mask >> pin) & 1 == 1 {
+                return Err(GpioError::DuplicatePin(pin));
+            }
+            mask |= 1 << pin;
+        }
+        Ok(Self { pins, mask })
+    }
+
+    /// Returns the pins of the group as a pin mask.
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    /// Sets every pin of the group to be an output pin.
+    pub unsafe fn set_output(&self) {
+        for &pin in self.pins.iter() {
+            gpio_set_output(pin).unwrap();
+        }
+    }
+
+    /// Drives the low `N` bits of `value` onto the pins.
+    /// Precondition: the pins are outputs
+    pub unsafe fn write(&self, value: u32) {
+        let mut set = 0u64;
+        for (i, &pin) in self.pins.iter().enumerate() {
+            if (value >> i) & 1 == 1 {
+                set |= 1 << pin;
+            }
+        }
+        gpio_write_mask(set, self.mask & !set).unwrap();
+    }
+
+    /// Returns the pin levels as an `N`-bit value.
+    pub unsafe fn read(&self) -> u32 {
+        let levels = gpio_read_all();
+        let mut value = 0;
+        for (i, &pin) in self.pins.iter().enumerate() {
+            value |= (((levels >> pin) & 1) as u32) << i;
+        }
+        value
+    }
+}
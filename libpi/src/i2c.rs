@@ -0,0 +1,371 @@
+//! I2C master driver for the BSC1 controller on GPIO 2 (SDA) and 3 (SCL),
+//! which have pull-ups on the board. Transfers are polled and use 7-bit
+//! addresses.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::io::*;
+use crate::bits::*;
+use crate::gpio::*;
+use crate::pin::*;
+use crate::time::*;
+use crate::mailbox::*;
+
+const BSC1_BASE: u32 = 0x20804000;
+const BSC_C: u32 = BSC1_BASE;
+const BSC_S: u32 = BSC1_BASE + 0x04;
+const BSC_DLEN: u32 = BSC1_BASE + 0x08;
+const BSC_A: u32 = BSC1_BASE + 0x0C;
+const BSC_FIFO: u32 = BSC1_BASE + 0x10;
+const BSC_DIV: u32 = BSC1_BASE + 0x14;
+const BSC_CLKT: u32 = BSC1_BASE + 0x1C;
+
+// BSC_C bits
+const C_READ: u8 = 0;
+const C_CLEAR: u8 = 4;
+const C_ST: u8 = 7;
+const C_I2CEN: u8 = 15;
+
+// BSC_S bits
+const S_TA: u8 = 0;
+const S_DONE: u8 = 1;
+const S_TXD: u8 = 4;
+const S_RXD: u8 = 5;
+const S_ERR: u8 = 8;
+const S_CLKT: u8 = 9;
+
+const I2C_SDA: u8 = 2;
+const I2C_SCL: u8 = 3;
+
+/// Depth of the BSC FIFO.
+const I2C_FIFO_LEN: usize = 16;
+
+/// Upper bound on a whole transfer, in case the bus is stuck.
+const I2C_TIMEOUT_MS: u32 = 100;
+
+/// Errors reported by I2C transfers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cError {
+    /// The address is not a 7-bit address.
+    InvalidAddress(u8),
+    /// The device did not acknowledge its address or a data byte.
+    Nack,
+    /// The device held SCL low for longer than the clock-stretch timeout.
+    ClockStretchTimeout,
+    /// The transfer did not finish within `I2C_TIMEOUT_MS`.
+    Timeout,
+    /// The write part of `i2c_write_read()` does not fit in the FIFO.
+    WriteTooLong,
+    /// The controller cannot run the sequence of operations without a stop
+    /// between them, e.g. a write after a read.
+    Unsupported,
+}
+
+/// Bus settings applied by `i2c_init()`.
+#[derive(Clone, Copy, Debug)]
+pub struct I2cConfig {
+    /// Requested SCL rate.
+    pub hz: u32,
+    /// How many SCL cycles a device may stretch the clock; 0 disables the
+    /// timeout.
+    pub clock_stretch_timeout: u16,
+}
+
+impl I2cConfig {
+    pub const DEFAULT: Self = Self {
+        hz: 100_000,
+        clock_stretch_timeout: 0x40,
+    };
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Routes GPIO 2/3 to BSC1, enables the controller and returns the actual
+/// SCL rate, or an error if one of the pins is claimed or in use by another
+/// driver.
+pub unsafe fn i2c_init(cfg: &I2cConfig) -> Result<u32, GpioError> {
+    gpio_reserve_all(&[I2C_SDA, I2C_SCL])?;
+    for pin in [I2C_SDA, I2C_SCL] {
+        gpio_set_fn(pin, 0b100).unwrap(); // ALT0
+    }
+    put32(BSC_C, 1 << C_I2CEN | 0b11 << C_CLEAR);
+    put32(BSC_S, 1 << S_CLKT | 1 << S_ERR | 1 << S_DONE);
+    put32(BSC_CLKT, cfg.clock_stretch_timeout as u32);
+    dsb();
+    Ok(i2c_set_hz(cfg.hz))
+}
+
+/// Disables the controller and returns GPIO 2/3 to inputs.
+pub unsafe fn i2c_disable() {
+    put32(BSC_C, 0b11 << C_CLEAR);
+    dsb();
+    for pin in [I2C_SDA, I2C_SCL] {
+        gpio_set_input(pin).unwrap();
+        gpio_unreserve(pin);
+    }
+}
+
+/// Sets SCL as close to `hz` as the divider allows without exceeding it and
+/// returns the actual rate.
+pub unsafe fn i2c_set_hz(hz: u32) -> u32 {
+    let core = mbox_core_clock();
+    // the divider is rounded down to even by the hardware; 0 means 32768
+    let cdiv = (core.div_ceil(hz.max(1)) + 1) & !1;
+    let cdiv = cdiv.clamp(2, 32768);
+    put32(BSC_DIV, cdiv & 0xFFFF);
+    dsb();
+    core / cdiv
+}
+
+/// Programs the slave address and length and clears the FIFO and the status
+/// flags of the previous transfer.
+unsafe fn i2c_setup(addr: u8, len: usize) -> Result<(), I2cError> {
+    if addr > 0x7F {
+        return Err(I2cError::InvalidAddress(addr));
+    }
+    put32(BSC_A, addr as u32);
+    put32(BSC_DLEN, len as u32);
+    put32(BSC_S, 1 << S_CLKT | 1 << S_ERR | 1 << S_DONE);
+    put32(BSC_C, 1 << C_I2CEN | 0b11 << C_CLEAR);
+    dsb();
+    Ok(())
+}
+
+/// Starts a transfer set up with `i2c_setup()`.
+unsafe fn i2c_go(read: bool) {
+    put32(BSC_C, 1 << C_I2CEN | 1 << C_ST | (read as u32) << C_READ);
+    dsb();
+}
+
+/// Returns the error flagged in the status register `s`, if any.
+fn i2c_status_error(s: u32) -> Result<(), I2cError> {
+    if bit_is_set(s, S_ERR) {
+        Err(I2cError::Nack)
+    } else if bit_is_set(s, S_CLKT) {
+        Err(I2cError::ClockStretchTimeout)
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits for the controller to release the bus, then reports the outcome of
+/// the transfer.
+unsafe fn i2c_finish(timer: &Timer) -> Result<(), I2cError> {
+    let mut s = get32(BSC_S);
+    while bit_is_set(s, S_TA) && !timer.done() {
+        s = get32(BSC_S);
+    }
+    put32(BSC_S, 1 << S_CLKT | 1 << S_ERR | 1 << S_DONE);
+    dsb();
+    i2c_status_error(s)?;
+    if bit_is_set(s, S_TA) { Err(I2cError::Timeout) } else { Ok(()) }
+}
+
+/// Feeds `data` into the FIFO of a running write transfer.
+unsafe fn i2c_fill(data: &[u8], timer: &Timer) -> Result<(), I2cError> {
+    let mut sent = 0;
+    while sent < data.len() {
+        let s = get32(BSC_S);
+        i2c_status_error(s)?;
+        if bit_is_set(s, S_TXD) {
+            put32(BSC_FIFO, data[sent] as u32);
+            sent += 1;
+        } else if bit_is_set(s, S_DONE) || timer.done() {
+            return Err(I2cError::Timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Drains the FIFO of a running read transfer into `buf`.
+unsafe fn i2c_drain(buf: &mut [u8], timer: &Timer) -> Result<(), I2cError> {
+    let mut received = 0;
+    while received < buf.len() {
+        let s = get32(BSC_S);
+        i2c_status_error(s)?;
+        if bit_is_set(s, S_RXD) {
+            buf[received] = get32(BSC_FIFO) as u8;
+            received += 1;
+        } else if timer.done() {
+            return Err(I2cError::Timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Writes `len` bytes, taken from `parts` in order, to the device at `addr`
+/// as a single transfer.
+unsafe fn i2c_write_parts<'a>(
+    addr: u8,
+    len: usize,
+    mut parts: impl Iterator<Item = &'a [u8]>
+) -> Result<(), I2cError> {
+    let timer = Timer::set(I2C_TIMEOUT_MS);
+    i2c_setup(addr, len)?;
+    i2c_go(false);
+    let filled = parts.try_for_each(|data| i2c_fill(data, &timer));
+    i2c_finish(&timer).and(filled)
+}
+
+/// Reads `len` bytes from the device at `addr` as a single transfer, filling
+/// `parts` in order.
+unsafe fn i2c_read_parts<'a>(
+    addr: u8,
+    len: usize,
+    mut parts: impl Iterator<Item = &'a mut [u8]>
+) -> Result<(), I2cError> {
+    let timer = Timer::set(I2C_TIMEOUT_MS);
+    i2c_setup(addr, len)?;
+    i2c_go(true);
+    let drained = parts.try_for_each(|buf| i2c_drain(buf, &timer));
+    i2c_finish(&timer).and(drained)
+}
+
+/// Writes `data` to the device at `addr`, then reads `len` bytes into `parts`
+/// after a repeated start; see `i2c_write_read()`.
+unsafe fn i2c_write_read_parts<'a>(
+    addr: u8,
+    data: &[u8],
+    len: usize,
+    mut parts: impl Iterator<Item = &'a mut [u8]>
+) -> Result<(), I2cError> {
+    if data.len() > I2C_FIFO_LEN {
+        return Err(I2cError::WriteTooLong);
+    }
+    if data.is_empty() {
+        return i2c_read_parts(addr, len, parts);
+    }
+    let timer = Timer::set(I2C_TIMEOUT_MS);
+    i2c_setup(addr, data.len())?;
+    for &b in data {
+        put32(BSC_FIFO, b as u32);
+    }
+    i2c_go(false);
+    // once the write is active, a new start is issued as a repeated start
+    // when it completes instead of a stop
+    while !bit_is_set(get32(BSC_S), S_TA) {
+        if bit_is_set(get32(BSC_S), S_DONE) || timer.done() {
+            i2c_finish(&timer)?;
+            return Err(I2cError::Timeout);
+        }
+    }
+    put32(BSC_DLEN, len as u32);
+    i2c_go(true);
+    let drained = parts.try_for_each(|buf| i2c_drain(buf, &timer));
+    i2c_finish(&timer).and(drained)
+}
+
+/// Writes `data` to the device at `addr`.
+pub unsafe fn i2c_write(addr: u8, data: &[u8]) -> Result<(), I2cError> {
+    i2c_write_parts(addr, data.len(), core::iter::once(data))
+}
+
+/// Reads `buf.len()` bytes from the device at `addr`.
+pub unsafe fn i2c_read(addr: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+    i2c_read_parts(addr, buf.len(), core::iter::once(buf))
+}
+
+/// Writes `data` to the device at `addr`, then reads `buf.len()` bytes after a
+/// repeated start, e.g. to read a register. `data` must fit in the 16-byte
+/// FIFO: the read is queued while the write is still on the bus.
+pub unsafe fn i2c_write_read(addr: u8, data: &[u8], buf: &mut [u8]) -> Result<(), I2cError> {
+    i2c_write_read_parts(addr, data, buf.len(), core::iter::once(buf))
+}
+
+/// Set once `I2c::take()` has handed out the bus.
+static I2C_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Owner of the BSC1 bus, implementing `embedded_hal::i2c::I2c`.
+pub struct I2c {
+    _private: (),
+}
+
+impl I2c {
+    /// Initializes BSC1 with `cfg` and returns the bus the first time it is
+    /// called, `None` after. Fails if GPIO 2 or 3 is claimed.
+    pub fn take(cfg: &I2cConfig) -> Result<Option<Self>, GpioError> {
+        if I2C_TAKEN.swap(true, Ordering::AcqRel) {
+            return Ok(None);
+        }
+        if let Err(e) = unsafe { i2c_init(cfg) } {
+            I2C_TAKEN.store(false, Ordering::Release);
+            return Err(e);
+        }
+        Ok(Some(Self { _private: () }))
+    }
+}
+
+impl embedded_hal::i2c::Error for I2cError {
+    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+        use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+        match self {
+            I2cError::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_hal::i2c::ErrorType for I2c {
+    type Error = I2cError;
+}
+
+impl embedded_hal::i2c::I2c for I2c {
+    /// Runs the operations as one transfer: adjacent writes are merged, as are
+    /// adjacent reads, and writes followed by reads use a repeated start. The
+    /// controller cannot switch back from reading to writing without a stop,
+    /// so such sequences fail with `I2cError::Unsupported`, and the writes
+    /// before a read must fit in the FIFO together.
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>]
+    ) -> Result<(), I2cError> {
+        use embedded_hal::i2c::Operation;
+        let split = operations.iter()
+            .position(|op| matches!(op, Operation::Read(_)))
+            .unwrap_or(operations.len());
+        let (writes, reads) = operations.split_at_mut(split);
+        if reads.iter().any(|op| matches!(op, Operation::Write(_))) {
+            return Err(I2cError::Unsupported);
+        }
+        let len = |op: &Operation<'_>| match op {
+            Operation::Write(data) => data.len(),
+            Operation::Read(buf) => buf.len(),
+        };
+        let write_len: usize = writes.iter().map(len).sum();
+        let read_len: usize = reads.iter().map(len).sum();
+        if reads.is_empty() {
+            if writes.is_empty() {
+                return Ok(());
+            }
+            let write_parts = writes.iter().filter_map(|op| match op {
+                Operation::Write(data) => Some(&**data),
+                Operation::Read(_) => None,
+            });
+            return unsafe { i2c_write_parts(address, write_len, write_parts) };
+        }
+        if write_len > I2C_FIFO_LEN {
+            return Err(I2cError::WriteTooLong);
+        }
+        let mut data = [0u8; I2C_FIFO_LEN];
+        let mut n = 0;
+        for op in writes.iter() {
+            if let Operation::Write(part) = op {
+                data[n..n + part.len()].copy_from_slice(part);
+                n += part.len();
+            }
+        }
+        let read_parts = reads.iter_mut().filter_map(|op| match op {
+            Operation::Read(buf) => Some(&mut **buf),
+            Operation::Write(_) => None,
+        });
+        unsafe { i2c_write_read_parts(address, &data[..n], read_len, read_parts) }
+    }
+
+    fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), I2cError> {
+        unsafe { i2c_write_read(address, write, read) }
+    }
+}
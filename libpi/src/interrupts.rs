@@ -4,9 +4,12 @@
 use core::arch::{asm, global_asm};
 use crate::io::*;
 use crate::bits::*;
-use crate::uart::*;
 use crate::gpio::*;
-use crate::user::*;
+use crate::armtimer::*;
+use crate::alarm::*;
+use crate::spi::*;
+use crate::pl011::*;
+use crate::dma::*;
 
 const IRQ_BASE: u32 = 0x2000B200;
 const IRQ_BASIC_PENDING: u32 = IRQ_BASE;
@@ -21,11 +24,109 @@ const IRQ_DISABLE_2: u32 = IRQ_BASE + 0x20;
 const IRQ_DISABLE_BASIC: u32 = IRQ_BASE + 0x24;
 
 pub const GPIO_BASE: u32 = 0x20200000;
+pub const GPEDS0: u32 = GPIO_BASE + 0x40;
+pub const GPEDS1: u32 = GPIO_BASE + 0x44;
 pub const GPREN0: u32 = GPIO_BASE + 0x4C;
 pub const GPREN1: u32 = GPIO_BASE + 0x50;
 pub const GPFEN0: u32 = GPIO_BASE + 0x58;
 pub const GPFEN1: u32 = GPIO_BASE + 0x5C;
-pub const GPEDS0: u32 = GPIO_BASE + 0x40;
+pub const GPHEN0: u32 = GPIO_BASE + 0x64;
+pub const GPHEN1: u32 = GPIO_BASE + 0x68;
+pub const GPLEN0: u32 = GPIO_BASE + 0x70;
+pub const GPLEN1: u32 = GPIO_BASE + 0x74;
+pub const GPAREN0: u32 = GPIO_BASE + 0x7C;
+pub const GPAREN1: u32 = GPIO_BASE + 0x80;
+pub const GPAFEN0: u32 = GPIO_BASE + 0x88;
+pub const GPAFEN1: u32 = GPIO_BASE + 0x8C;
+
+/// IRQ_*_BASIC bit of the ARM timer.
+const ARM_TIMER_IRQ: u8 = 0;
+
+/// Peripheral IRQ number of gpio_int[0]; gpio_int[1..3] follow.
+const GPIO_INT0_IRQ: u8 = 49;
+
+/// Peripheral IRQ number of the SPI0 controller.
+pub const SPI_IRQ: u8 = 54;
+
+/// Peripheral IRQ number of the PL011 UART0.
+pub const UART0_IRQ: u8 = 57;
+
+/// Callback run on a GPIO event; receives the pin number.
+pub type GpioHandler = unsafe fn(u8);
+
+/// Registered GPIO event callbacks, indexed by pin.
+static mut GPIO_HANDLERS: [Option<GpioHandler>; GPIO_PINS as usize] =
+    [None; GPIO_PINS as usize];
+
+/// Number of GPIO interrupts or events nobody handled.
+static mut GPIO_SPURIOUS: u32 = 0;
+
+/// Pins that have raised events without a handler.
+static mut GPIO_SPURIOUS_PINS: u64 = 0;
+
+/// Kinds of GPIO event detection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Synchronous rising edge (GPREN)
+    Rising,
+    /// Synchronous falling edge (GPFEN)
+    Falling,
+    /// Pin is high (GPHEN)
+    High,
+    /// Pin is low (GPLEN)
+    Low,
+    /// Asynchronous rising edge (GPAREN)
+    AsyncRising,
+    /// Asynchronous falling edge (GPAFEN)
+    AsyncFalling,
+}
+
+impl EdgeKind {
+    pub const ALL: [EdgeKind; 6] = [
+        EdgeKind::Rising,
+        EdgeKind::Falling,
+        EdgeKind::High,
+        EdgeKind::Low,
+        EdgeKind::AsyncRising,
+        EdgeKind::AsyncFalling,
+    ];
+
+    /// Returns the bank 0 detect enable register for this kind.
+    fn register(self) -> u32 {
+        match self {
+            EdgeKind::Rising => GPREN0,
+            EdgeKind::Falling => GPFEN0,
+            EdgeKind::High => GPHEN0,
+            EdgeKind::Low => GPLEN0,
+            EdgeKind::AsyncRising => GPAREN0,
+            EdgeKind::AsyncFalling => GPAFEN0,
+        }
+    }
+}
+
+/// GPIO interrupt lines of the interrupt controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpioIrq {
+    /// gpio_int[0], pins 0-27
+    Bank0 = 0,
+    /// gpio_int[1], pins 28-45
+    Bank1 = 1,
+    /// gpio_int[2], pins 46-53
+    Bank2 = 2,
+    /// gpio_int[3], any pin
+    All = 3,
+}
+
+impl GpioIrq {
+    /// Returns the bank line `pin` raises.
+    pub fn of(pin: u8) -> Self {
+        match pin {
+            0..=27 => GpioIrq::Bank0,
+            28..=45 => GpioIrq::Bank1,
+            _ => GpioIrq::Bank2,
+        }
+    }
+}
 
 // UART code that is not working yet 
 const AUX_BASE: u32 = 0x20215000;
@@ -79,11 +180,11 @@ pub unsafe extern "C" fn disable_interrupts() {
     );
 }
 
-/// Interrupt handler code for IRQ interrupts 
+/// Interrupt handler code for IRQ interrupts. Runs on the IRQ-mode stack set
+/// up by `_start`.
 #[no_mangle]
 pub unsafe extern "C" fn interrupt_handler() {
     asm!(
-        "ldr sp, =0x90000", 
         "sub lr, lr, #4",
         "push {{r0-r12, lr}}",
         "mov r0, lr",
@@ -106,6 +207,7 @@ pub unsafe fn interrupt_init() {
     disable_interrupts();
     put32(IRQ_DISABLE_1, 0xFFFFFFFF);
     put32(IRQ_DISABLE_2, 0xFFFFFFFF);
+    put32(IRQ_DISABLE_BASIC, 0xFFFFFFFF);
 
     let vector_base = &_interrupt_table as *const u32 as u32;
     dsb(); 
@@ -113,71 +215,249 @@ pub unsafe fn interrupt_init() {
     dsb(); 
 }
 
-/// Enables rising edge detection interrupt on `pin`.
-/// Precondition: `pin < 32`
-pub unsafe fn gpio_rising_edge_init(pin: u8) {
-    if pin >= 32 {
-        return;
+/// Routes the ARM timer interrupt to the ARM.
+pub unsafe fn arm_timer_irq_enable() {
+    dsb();
+    put32(IRQ_ENABLE_BASIC, bit_set(0, ARM_TIMER_IRQ));
+    dsb();
+}
+
+/// Stops routing the ARM timer interrupt to the ARM.
+pub unsafe fn arm_timer_irq_disable() {
+    dsb();
+    put32(IRQ_DISABLE_BASIC, bit_set(0, ARM_TIMER_IRQ));
+    dsb();
+}
+
+/// Returns whether the ARM timer interrupt is pending.
+pub unsafe fn arm_timer_irq_pending() -> bool {
+    dsb();
+    let b = bit_is_set(get32(IRQ_BASIC_PENDING), ARM_TIMER_IRQ);
+    dsb();
+    b
+}
+
+/// Enables `kind` event detection on `pin`. Does not route the interrupt;
+/// see `gpio_irq_enable`.
+pub unsafe fn gpio_event_enable(pin: u8, kind: EdgeKind) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(kind.register(), pin);
+    dsb();
+    put32(addr, bit_set(get32(addr), bit));
+    dsb();
+    Ok(())
+}
+
+/// Disables `kind` event detection on `pin`.
+pub unsafe fn gpio_event_disable(pin: u8, kind: EdgeKind) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(kind.register(), pin);
+    dsb();
+    put32(addr, bit_clr(get32(addr), bit));
+    dsb();
+    Ok(())
+}
+
+/// Disables every kind of event detection on `pin` and clears its event.
+pub unsafe fn gpio_event_disable_all(pin: u8) -> Result<(), GpioError> {
+    for kind in EdgeKind::ALL {
+        gpio_event_disable(pin, kind)?;
     }
+    gpio_event_clear(pin)
+}
+
+/// Returns the register among `reg1`/`reg2` and the bit of peripheral IRQ `irq`.
+/// Precondition: `irq < 64`
+fn irq_bank(reg1: u32, reg2: u32, irq: u8) -> (u32, u8) {
+    assert!(irq < 64);
+    if irq < 32 { (reg1, irq) } else { (reg2, irq - 32) }
+}
+
+/// Routes peripheral interrupt `irq` (0-63) to the ARM.
+pub unsafe fn irq_enable(irq: u8) {
+    let (reg, bit) = irq_bank(IRQ_ENABLE_1, IRQ_ENABLE_2, irq);
+    dsb();
+    put32(reg, bit_set(0, bit));
     dsb();
-    put32(GPREN0, bit_set(get32(GPREN0), pin));
+}
+
+/// Stops routing peripheral interrupt `irq` (0-63) to the ARM.
+pub unsafe fn irq_disable(irq: u8) {
+    let (reg, bit) = irq_bank(IRQ_DISABLE_1, IRQ_DISABLE_2, irq);
     dsb();
-    put32(IRQ_ENABLE_2, bit_set(0, 17));
+    put32(reg, bit_set(0, bit));
     dsb();
 }
 
+/// Returns whether peripheral interrupt `irq` (0-63) is pending.
+pub unsafe fn irq_pending(irq: u8) -> bool {
+    let (reg, bit) = irq_bank(IRQ_PENDING_1, IRQ_PENDING_2, irq);
+    dsb();
+    let b = bit_is_set(get32(reg), bit);
+    dsb();
+    b
+}
+
+/// Routes GPIO interrupt line `line` to the ARM.
+pub unsafe fn gpio_irq_enable(line: GpioIrq) {
+    irq_enable(GPIO_INT0_IRQ + line as u8);
+}
+
+/// Stops routing GPIO interrupt line `line` to the ARM.
+pub unsafe fn gpio_irq_disable(line: GpioIrq) {
+    irq_disable(GPIO_INT0_IRQ + line as u8);
+}
+
+/// Returns whether GPIO interrupt line `line` is pending.
+pub unsafe fn gpio_irq_pending(line: GpioIrq) -> bool {
+    irq_pending(GPIO_INT0_IRQ + line as u8)
+}
+
+/// Enables `kind` event detection on `pin` and routes its bank's interrupt
+/// line.
+pub unsafe fn gpio_interrupt_init(pin: u8, kind: EdgeKind) -> Result<(), GpioError> {
+    gpio_event_enable(pin, kind)?;
+    gpio_irq_enable(GpioIrq::of(pin));
+    Ok(())
+}
+
+/// Enables rising edge detection interrupt on `pin`.
+pub unsafe fn gpio_rising_edge_init(pin: u8) -> Result<(), GpioError> {
+    gpio_interrupt_init(pin, EdgeKind::Rising)
+}
+
+/// Enables falling edge detection interrupt on `pin`.
+pub unsafe fn gpio_falling_edge_init(pin: u8) -> Result<(), GpioError> {
+    gpio_interrupt_init(pin, EdgeKind::Falling)
+}
+
 /// Returns whether GPIO event is detected at `pin`.
-/// Precondition: `pin < 32`
-pub unsafe fn gpio_event_detected(pin: u8) -> bool {
-    if pin >= 32 {
-        false
-    } else {
-        dsb(); 
-        let b = bit_is_set(get32(GPEDS0), pin);
-        dsb();
-        b
-    }
+pub unsafe fn gpio_event_detected(pin: u8) -> Result<bool, GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(GPEDS0, pin);
+    dsb(); 
+    let b = bit_is_set(get32(addr), bit);
+    dsb();
+    Ok(b)
 }
 
 /// Clears the GPIO interrupt pending bit at `pin`.
-/// Precondition: `pin < 32`
-pub unsafe fn gpio_event_clear(pin: u8) {
-    if pin >= 32 {
-        return;
-    }
+pub unsafe fn gpio_event_clear(pin: u8) -> Result<(), GpioError> {
+    gpio_check_pin(pin)?;
+    let (addr, bit) = gpio_bank(GPEDS0, pin);
+    dsb();
+    put32(addr, bit_set(0, bit));
     dsb();
-    put32(GPEDS0, bit_set(0, pin));
+    Ok(())
+}
+
+/// Returns the detected events of all pins as a mask (bit `i` = pin `i`).
+pub unsafe fn gpio_events_pending() -> u64 {
+    dsb();
+    let events = (get32(GPEDS1) as u64) << 32 | get32(GPEDS0) as u64;
+    dsb();
+    events
+}
+
+/// Clears the detected events of the pins set in `mask`.
+pub unsafe fn gpio_events_clear(mask: u64) {
+    dsb();
+    if mask as u32 != 0 {
+        put32(GPEDS0, mask as u32);
+    }
+    if (mask >> 32) as u32 != 0 {
+        put32(GPEDS1, (mask >> 32) as u32);
+    }
     dsb();
 }
 
 /// Interrupt vector for IRQ interrupts. 
 #[no_mangle]
 pub unsafe extern "C" fn interrupt_vector() {
-    gpio_set_on(5);
-    dsb();
+    if arm_timer_irq_pending() {
+        arm_timer_irq_handler();
+    }
+    if irq_pending(CompareChannel::C1.irq()) || irq_pending(CompareChannel::C3.irq()) {
+        alarm_irq_handler();
+    }
+    if irq_pending(SPI_IRQ) {
+        spi_irq_handler();
+    }
+    if irq_pending(UART0_IRQ) {
+        pl011_irq_handler();
+    }
+    if dma_irq_pending() {
+        dma_irq_handler();
+    }
+    let lines = [GpioIrq::Bank0, GpioIrq::Bank1, GpioIrq::Bank2, GpioIrq::All];
+    if lines.iter().any(|&line| gpio_irq_pending(line)) {
+        gpio_irq_handler();
+    }
+}
 
-    if !gpio_event_detected(21) {
+/// Clears the pending events of pins with a callback, then runs each of
+/// those callbacks once, so an edge arriving during a callback stays pending.
+/// Events on pins without a callback are counted as spurious, as is an
+/// interrupt with no event at all; since such a pin would keep the interrupt
+/// asserted, its event detection is turned off.
+pub unsafe fn gpio_irq_handler() {
+    let events = gpio_events_pending();
+    if events == 0 {
+        GPIO_SPURIOUS += 1;
         return;
     }
 
-    // Custom code that controls mini-UART and GPIO output using keystrokes.
-    while gpio_read(21) == 1 {
-        while uart_has_data() {
-            let c = uart_get8() as char;
-            uart_put8(c as u8);
-            match c {
-                'g' => gpio_toggle(20),
-                'r' => gpio_toggle(25),
-                i if i.is_numeric() => spawn(i),
-                _ => continue
-            };
+    let mut handled = 0u64;
+    for pin in 0..GPIO_PINS {
+        if (events >> pin) & 1 == 0 {
+            continue;
+        }
+        if GPIO_HANDLERS[pin as usize].is_some() {
+            handled |= 1 << pin;
+        } else {
+            GPIO_SPURIOUS += 1;
+            GPIO_SPURIOUS_PINS |= 1 << pin;
+            gpio_event_disable_all(pin).unwrap();
+        }
+    }
+    gpio_events_clear(handled);
+    for pin in 0..GPIO_PINS {
+        if (handled >> pin) & 1 == 1 {
+            if let Some(handler) = GPIO_HANDLERS[pin as usize] {
+                handler(pin);
+            }
         }
-        dsb();
     }
+}
+
+/// Registers `handler` to run from the IRQ handler when a `kind` event is
+/// detected on `pin`, and enables that detection. Calling it again for the
+/// same pin replaces the handler and adds `kind` to the detected events;
+/// returns the handler it replaced, if any.
+pub unsafe fn gpio_on_event(
+    pin: u8,
+    kind: EdgeKind,
+    handler: GpioHandler
+) -> Result<Option<GpioHandler>, GpioError> {
+    gpio_check_pin(pin)?;
+    let old = GPIO_HANDLERS[pin as usize].replace(handler);
+    gpio_event_clear(pin)?;
+    gpio_interrupt_init(pin, kind)?;
+    Ok(old)
+}
+
+/// Unregisters the handler of `pin`, disables its event detection and clears
+/// any event it already latched.
+pub unsafe fn gpio_off_event(pin: u8) -> Result<(), GpioError> {
+    gpio_event_disable_all(pin)?;
+    GPIO_HANDLERS[pin as usize] = None;
+    gpio_event_clear(pin)
+}
 
-    // Exit code 
-    gpio_event_clear(21);
-    gpio_set_off(5);
+/// Returns the number of spurious GPIO events so far and the mask of pins
+/// that raised events without a handler.
+pub unsafe fn gpio_spurious_events() -> (u32, u64) {
+    (GPIO_SPURIOUS, GPIO_SPURIOUS_PINS)
 }
 
 /// returns the current value vector base is set to.
@@ -3,10 +3,32 @@
 pub mod bits;
 pub mod io;
 pub mod gpio;
+pub mod pin;
+pub mod debounce;
+pub mod clock;
+pub mod pwm;
+pub mod softpwm;
+pub mod spi;
+pub mod i2c;
+pub mod softspi;
+pub mod softi2c;
+pub mod onewire;
+pub mod serial;
 pub mod uart;
+pub mod pl011;
 pub mod interrupts;
+pub mod armtimer;
 pub mod tstack;
 pub mod threads;
 pub mod time;
+pub mod alarm;
 pub mod user;
-pub mod debug;
\ No newline at end of file
+pub mod debug;
+pub mod memmap;
+pub mod mailbox;
+pub mod dma;
+pub mod rng;
+pub mod watchdog;
+pub mod font;
+pub mod framebuffer;
+pub mod boot;
\ No newline at end of file
@@ -0,0 +1,378 @@
+//! Mailbox property interface (channel 8) to the VideoCore firmware.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::time::*;
+use crate::memmap::Region;
+
+const MBOX_BASE: u32 = 0x2000B880;
+const MBOX_READ: u32 = MBOX_BASE;
+const MBOX_STATUS: u32 = MBOX_BASE + 0x18;
+const MBOX_WRITE: u32 = MBOX_BASE + 0x20;
+
+const MBOX_FULL: u8 = 31;
+const MBOX_EMPTY: u8 = 30;
+
+/// ARM to VideoCore property channel.
+const MBOX_CH_PROP: u32 = 8;
+
+/// ARM physical addresses as seen by the VideoCore (L2-coherent alias).
+const BUS_ALIAS: u32 = 0x40000000;
+
+const MBOX_REQUEST: u32 = 0;
+const MBOX_SUCCESS: u32 = 0x80000000;
+const MBOX_TAG_RESPONSE: u8 = 31;
+
+/// Core clock the firmware configures unless `config.txt` overrides it.
+const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// How long to wait for the firmware before giving up.
+const MBOX_TIMEOUT_MS: u32 = 500;
+
+/// Number of 32-bit words in a property message, header and end tag included.
+const MBOX_WORDS: usize = 64;
+
+pub const TAG_GET_BOARD_MODEL: u32 = 0x00010001;
+pub const TAG_GET_BOARD_REVISION: u32 = 0x00010002;
+pub const TAG_GET_BOARD_SERIAL: u32 = 0x00010004;
+pub const TAG_GET_ARM_MEMORY: u32 = 0x00010005;
+pub const TAG_GET_VC_MEMORY: u32 = 0x00010006;
+pub const TAG_GET_POWER_STATE: u32 = 0x00020001;
+pub const TAG_SET_POWER_STATE: u32 = 0x00028001;
+pub const TAG_GET_CLOCK_RATE: u32 = 0x00030002;
+pub const TAG_GET_MAX_CLOCK_RATE: u32 = 0x00030004;
+pub const TAG_GET_TEMPERATURE: u32 = 0x00030006;
+pub const TAG_GET_MIN_CLOCK_RATE: u32 = 0x00030007;
+pub const TAG_GET_MAX_TEMPERATURE: u32 = 0x0003000A;
+pub const TAG_SET_CLOCK_RATE: u32 = 0x00038002;
+pub const TAG_ALLOCATE_BUFFER: u32 = 0x00040001;
+pub const TAG_GET_PITCH: u32 = 0x00040008;
+pub const TAG_SET_PHYSICAL_SIZE: u32 = 0x00048003;
+pub const TAG_SET_VIRTUAL_SIZE: u32 = 0x00048004;
+pub const TAG_SET_DEPTH: u32 = 0x00048005;
+pub const TAG_SET_PIXEL_ORDER: u32 = 0x00048006;
+pub const TAG_SET_VIRTUAL_OFFSET: u32 = 0x00048009;
+
+/// Errors reported by a mailbox transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MboxError {
+    /// The firmware did not accept or answer the message in time.
+    Timeout,
+    /// The firmware returned a response code other than success.
+    Failed(u32),
+    /// The firmware did not process the tag with this id.
+    TagNotHandled(u32),
+    /// The message does not fit in the property buffer.
+    Overflow,
+}
+
+/// Clock ids understood by the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+}
+
+/// Power domain ids understood by the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Device {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// A typed property tag. Use `PropertyMessage::push_raw` for tags not listed.
+#[derive(Clone, Copy, Debug)]
+pub enum Tag {
+    BoardModel,
+    BoardRevision,
+    BoardSerial,
+    ArmMemory,
+    VcMemory,
+    PowerState(Device),
+    /// device, on, wait until the new state is stable
+    SetPowerState(Device, bool, bool),
+    ClockRate(Clock),
+    MinClockRate(Clock),
+    MaxClockRate(Clock),
+    /// clock, rate in Hz, skip setting turbo
+    SetClockRate(Clock, u32, bool),
+    Temperature,
+    MaxTemperature,
+    /// alignment in bytes
+    AllocateBuffer(u32),
+    Pitch,
+    /// width, height
+    SetPhysicalSize(u32, u32),
+    /// width, height
+    SetVirtualSize(u32, u32),
+    /// bits per pixel
+    SetDepth(u32),
+    /// 0 = BGR, 1 = RGB
+    SetPixelOrder(u32),
+    /// x, y
+    SetVirtualOffset(u32, u32),
+}
+
+impl Tag {
+    /// Returns the tag id, request values, and response length in words.
+    fn encode(self) -> (u32, [u32; 3], usize, usize) {
+        match self {
+            Tag::BoardModel => (TAG_GET_BOARD_MODEL, [0; 3], 0, 1),
+            Tag::BoardRevision => (TAG_GET_BOARD_REVISION, [0; 3], 0, 1),
+            Tag::BoardSerial => (TAG_GET_BOARD_SERIAL, [0; 3], 0, 2),
+            Tag::ArmMemory => (TAG_GET_ARM_MEMORY, [0; 3], 0, 2),
+            Tag::VcMemory => (TAG_GET_VC_MEMORY, [0; 3], 0, 2),
+            Tag::PowerState(d) => (TAG_GET_POWER_STATE, [d as u32, 0, 0], 1, 2),
+            Tag::SetPowerState(d, on, wait) => {
+                let state = (on as u32) | (wait as u32) << 1;
+                (TAG_SET_POWER_STATE, [d as u32, state, 0], 2, 2)
+            }
+            Tag::ClockRate(c) => (TAG_GET_CLOCK_RATE, [c as u32, 0, 0], 1, 2),
+            Tag::MinClockRate(c) => (TAG_GET_MIN_CLOCK_RATE, [c as u32, 0, 0], 1, 2),
+            Tag::MaxClockRate(c) => (TAG_GET_MAX_CLOCK_RATE, [c as u32, 0, 0], 1, 2),
+            Tag::SetClockRate(c, hz, skip_turbo) => {
+                (TAG_SET_CLOCK_RATE, [c as u32, hz, skip_turbo as u32], 3, 2)
+            }
+            Tag::Temperature => (TAG_GET_TEMPERATURE, [0; 3], 1, 2),
+            Tag::MaxTemperature => (TAG_GET_MAX_TEMPERATURE, [0; 3], 1, 2),
+            Tag::AllocateBuffer(align) => (TAG_ALLOCATE_BUFFER, [align, 0, 0], 1, 2),
+            Tag::Pitch => (TAG_GET_PITCH, [0; 3], 0, 1),
+            Tag::SetPhysicalSize(w, h) => (TAG_SET_PHYSICAL_SIZE, [w, h, 0], 2, 2),
+            Tag::SetVirtualSize(w, h) => (TAG_SET_VIRTUAL_SIZE, [w, h, 0], 2, 2),
+            Tag::SetDepth(bpp) => (TAG_SET_DEPTH, [bpp, 0, 0], 1, 1),
+            Tag::SetPixelOrder(order) => (TAG_SET_PIXEL_ORDER, [order, 0, 0], 1, 1),
+            Tag::SetVirtualOffset(x, y) => (TAG_SET_VIRTUAL_OFFSET, [x, y, 0], 2, 2),
+        }
+    }
+}
+
+/// Handle to a tag inside a `PropertyMessage`, used to read its response.
+#[derive(Clone, Copy, Debug)]
+pub struct TagSlot {
+    tag: u32,
+    offset: usize,
+    words: usize,
+}
+
+/// The firmware requires the message to be 16-byte aligned.
+#[repr(C, align(16))]
+struct MboxBuffer([u32; MBOX_WORDS]);
+
+/// A property channel message built from one or more tags.
+pub struct PropertyMessage {
+    buf: MboxBuffer,
+    len: usize,
+}
+
+impl PropertyMessage {
+    /// Returns an empty request.
+    pub fn new() -> Self {
+        Self {
+            buf: MboxBuffer([0; MBOX_WORDS]),
+            len: 2,
+        }
+    }
+
+    /// Appends typed tag `tag` and returns its slot.
+    pub fn push(&mut self, tag: Tag) -> Result<TagSlot, MboxError> {
+        let (id, values, n, response_words) = tag.encode();
+        self.push_raw(id, &values[..n], response_words)
+    }
+
+    /// Appends tag `id` with request `values`, reserving room for a
+    /// `response_words`-word response, and returns its slot.
+    pub fn push_raw(
+        &mut self,
+        id: u32,
+        values: &[u32],
+        response_words: usize
+    ) -> Result<TagSlot, MboxError> {
+        let words = values.len().max(response_words);
+        // tag header (3 words) + values + end tag
+        if self.len + 3 + words + 1 > MBOX_WORDS {
+            return Err(MboxError::Overflow);
+        }
+        let b = &mut self.buf.0;
+        b[self.len] = id;
+        b[self.len + 1] = (words * 4) as u32;
+        b[self.len + 2] = MBOX_REQUEST;
+        let offset = self.len + 3;
+        b[offset..offset + words].fill(0);
+        b[offset..offset + values.len()].copy_from_slice(values);
+        self.len = offset + words;
+        Ok(TagSlot { tag: id, offset, words })
+    }
+
+    /// Sends the message on the property channel and waits for the firmware
+    /// to answer, checking the overall response code.
+    pub unsafe fn send(&mut self) -> Result<(), MboxError> {
+        let b = &mut self.buf.0;
+        b[self.len] = 0; // end tag
+        b[0] = ((self.len + 1) * 4) as u32;
+        b[1] = MBOX_REQUEST;
+
+        let addr = b.as_ptr() as u32 | BUS_ALIAS;
+        mbox_write(MBOX_CH_PROP, addr)?;
+        while mbox_read(MBOX_CH_PROP)? != addr {}
+
+        match self.buf.0[1] {
+            MBOX_SUCCESS => Ok(()),
+            code => Err(MboxError::Failed(code)),
+        }
+    }
+
+    /// Returns the response values of `slot`.
+    /// Precondition: `send()` has returned `Ok`.
+    pub fn response(&self, slot: TagSlot) -> Result<&[u32], MboxError> {
+        let code = self.buf.0[slot.offset - 1];
+        if !bit_is_set(code, MBOX_TAG_RESPONSE) {
+            return Err(MboxError::TagNotHandled(slot.tag));
+        }
+        let words = ((code & !(1 << MBOX_TAG_RESPONSE)) as usize).div_ceil(4);
+        Ok(&self.buf.0[slot.offset..slot.offset + words.min(slot.words)])
+    }
+}
+
+impl Default for PropertyMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes `data` (upper 28 bits) to mailbox `channel`.
+unsafe fn mbox_write(channel: u32, data: u32) -> Result<(), MboxError> {
+    let timer = Timer::set(MBOX_TIMEOUT_MS);
+    while bit_is_set(get32(MBOX_STATUS), MBOX_FULL) {
+        if timer.done() {
+            return Err(MboxError::Timeout);
+        }
+    }
+    dsb();
+    put32(MBOX_WRITE, (data & !0xF) | channel);
+    dsb();
+    Ok(())
+}
+
+/// Returns the next message (upper 28 bits) received on mailbox `channel`.
+unsafe fn mbox_read(channel: u32) -> Result<u32, MboxError> {
+    let timer = Timer::set(MBOX_TIMEOUT_MS);
+    loop {
+        while bit_is_set(get32(MBOX_STATUS), MBOX_EMPTY) {
+            if timer.done() {
+                return Err(MboxError::Timeout);
+            }
+        }
+        dsb();
+        let v = get32(MBOX_READ);
+        dsb();
+        if v & 0xF == channel {
+            return Ok(v & !0xF);
+        }
+    }
+}
+
+/// Sends a message holding only `tag` and returns the first `N` response words.
+unsafe fn mbox_query<const N: usize>(tag: Tag) -> Result<[u32; N], MboxError> {
+    let mut msg = PropertyMessage::new();
+    let slot = msg.push(tag)?;
+    msg.send()?;
+    let values = msg.response(slot)?;
+    let mut out = [0; N];
+    let n = values.len().min(N);
+    out[..n].copy_from_slice(&values[..n]);
+    Ok(out)
+}
+
+/// Returns the board model.
+pub unsafe fn mbox_board_model() -> Result<u32, MboxError> {
+    Ok(mbox_query::<1>(Tag::BoardModel)?[0])
+}
+
+/// Returns the board revision code.
+pub unsafe fn mbox_board_revision() -> Result<u32, MboxError> {
+    Ok(mbox_query::<1>(Tag::BoardRevision)?[0])
+}
+
+/// Returns the 64-bit board serial number.
+pub unsafe fn mbox_board_serial() -> Result<u64, MboxError> {
+    let [lo, hi] = mbox_query(Tag::BoardSerial)?;
+    Ok((hi as u64) << 32 | lo as u64)
+}
+
+/// Returns the region of physical memory owned by the ARM.
+pub unsafe fn mbox_arm_memory() -> Result<Region, MboxError> {
+    let [base, size] = mbox_query(Tag::ArmMemory)?;
+    Ok(Region { start: base, end: base + size })
+}
+
+/// Returns the region of physical memory owned by the VideoCore.
+pub unsafe fn mbox_vc_memory() -> Result<Region, MboxError> {
+    let [base, size] = mbox_query(Tag::VcMemory)?;
+    Ok(Region { start: base, end: base + size })
+}
+
+/// Returns the current rate of `clock` in Hz.
+pub unsafe fn mbox_clock_rate(clock: Clock) -> Result<u32, MboxError> {
+    Ok(mbox_query::<2>(Tag::ClockRate(clock))?[1])
+}
+
+/// Returns the core (VPU) clock rate in Hz, which also clocks the APB
+/// peripherals, assuming the 250 MHz default if the firmware does not answer.
+pub unsafe fn mbox_core_clock() -> u32 {
+    mbox_clock_rate(Clock::Core).unwrap_or(DEFAULT_CORE_CLOCK)
+}
+
+/// Returns the maximum rate of `clock` in Hz.
+pub unsafe fn mbox_max_clock_rate(clock: Clock) -> Result<u32, MboxError> {
+    Ok(mbox_query::<2>(Tag::MaxClockRate(clock))?[1])
+}
+
+/// Returns the minimum rate of `clock` in Hz.
+pub unsafe fn mbox_min_clock_rate(clock: Clock) -> Result<u32, MboxError> {
+    Ok(mbox_query::<2>(Tag::MinClockRate(clock))?[1])
+}
+
+/// Sets `clock` to `hz` and returns the rate actually set. Fails if the
+/// firmware rejects the clock id.
+pub unsafe fn mbox_set_clock_rate(clock: Clock, hz: u32) -> Result<u32, MboxError> {
+    let [id, rate] = mbox_query(Tag::SetClockRate(clock, hz, false))?;
+    if id == 0 {
+        return Err(MboxError::Failed(id));
+    }
+    Ok(rate)
+}
+
+/// Powers `device` on or off, waiting until the new state is stable. Returns
+/// whether the device is now on.
+pub unsafe fn mbox_set_power(device: Device, on: bool) -> Result<bool, MboxError> {
+    let [_, state] = mbox_query(Tag::SetPowerState(device, on, true))?;
+    if bit_is_set(state, 1) {
+        return Err(MboxError::Failed(state)); // device does not exist
+    }
+    Ok(bit_is_set(state, 0))
+}
+
+/// Returns the SoC temperature in thousandths of a degree Celsius.
+pub unsafe fn mbox_temperature() -> Result<u32, MboxError> {
+    Ok(mbox_query::<2>(Tag::Temperature)?[1])
+}
+
+/// Returns the SoC temperature limit in thousandths of a degree Celsius.
+pub unsafe fn mbox_max_temperature() -> Result<u32, MboxError> {
+    Ok(mbox_query::<2>(Tag::MaxTemperature)?[1])
+}
@@ -0,0 +1,214 @@
+//! Memory map introspection using the section symbols exported by `memory.ld`.
+
+use crate::debug::*;
+use crate::uart::*;
+
+extern "C" {
+    static __symbol_exec_start__: u8;
+    static __symbol_exec_end__: u8;
+    static __symbol_code_start__: u8;
+    static __symbol_code_end__: u8;
+    static __symbol_rodata_start__: u8;
+    static __symbol_rodata_end__: u8;
+    static __symbol_data_start__: u8;
+    static __symbol_data_end__: u8;
+    static __symbol_bss_start__: u8;
+    static __symbol_bss_end__: u8;
+    static __symbol_stack_begin__: u8;
+    static __symbol_stack_end__: u8;
+    static __symbol_svc_stack_start__: u8;
+    static __symbol_svc_stack_end__: u8;
+    static __symbol_irq_stack_start__: u8;
+    static __symbol_irq_stack_end__: u8;
+    static __symbol_fiq_stack_start__: u8;
+    static __symbol_fiq_stack_end__: u8;
+    static __symbol_abt_stack_start__: u8;
+    static __symbol_abt_stack_end__: u8;
+    static __symbol_und_stack_start__: u8;
+    static __symbol_und_stack_end__: u8;
+    static __symbol_sys_stack_start__: u8;
+    static __symbol_sys_stack_end__: u8;
+    static __symbol_tstack_start__: u8;
+    static __symbol_tstack_end__: u8;
+    static __symbol_interrupt_table_start__: u8;
+    static __symbol_interrupt_table_end__: u8;
+}
+
+/// A half-open range of physical addresses `[start, end)`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Region {
+    /// Returns the region delimited by linker symbols `start` and `end`.
+    fn from_symbols(start: &u8, end: &u8) -> Self {
+        Self {
+            start: start as *const u8 as u32,
+            end: end as *const u8 as u32,
+        }
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn len(&self) -> u32 {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// Returns `true` if the region contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if `addr` lies inside the region.
+    pub fn contains(&self, addr: u32) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns `true` if the region shares at least one byte with `other`.
+    pub fn overlaps(&self, other: &Region) -> bool {
+        !self.is_empty() && !other.is_empty()
+            && self.start < other.end && other.start < self.end
+    }
+}
+
+/// Whole kernel image, from the first instruction to the end of the stacks.
+pub fn image() -> Region {
+    unsafe { Region::from_symbols(&__symbol_exec_start__, &__symbol_exec_end__) }
+}
+
+/// `.text`
+pub fn code() -> Region {
+    unsafe { Region::from_symbols(&__symbol_code_start__, &__symbol_code_end__) }
+}
+
+/// `.rodata`
+pub fn rodata() -> Region {
+    unsafe { Region::from_symbols(&__symbol_rodata_start__, &__symbol_rodata_end__) }
+}
+
+/// `.data`
+pub fn data() -> Region {
+    unsafe { Region::from_symbols(&__symbol_data_start__, &__symbol_data_end__) }
+}
+
+/// `.bss`
+pub fn bss() -> Region {
+    unsafe { Region::from_symbols(&__symbol_bss_start__, &__symbol_bss_end__) }
+}
+
+/// `.stack`, i.e. all exception-mode stacks together.
+pub fn stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_stack_begin__, &__symbol_stack_end__) }
+}
+
+/// Supervisor mode stack.
+pub fn svc_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_svc_stack_start__, &__symbol_svc_stack_end__) }
+}
+
+/// IRQ mode stack.
+pub fn irq_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_irq_stack_start__, &__symbol_irq_stack_end__) }
+}
+
+/// FIQ mode stack.
+pub fn fiq_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_fiq_stack_start__, &__symbol_fiq_stack_end__) }
+}
+
+/// Abort mode stack.
+pub fn abt_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_abt_stack_start__, &__symbol_abt_stack_end__) }
+}
+
+/// Undefined mode stack.
+pub fn und_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_und_stack_start__, &__symbol_und_stack_end__) }
+}
+
+/// System mode stack (banked with user mode).
+pub fn sys_stack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_sys_stack_start__, &__symbol_sys_stack_end__) }
+}
+
+/// `.tstack`, the thread stack pool.
+pub fn tstack() -> Region {
+    unsafe { Region::from_symbols(&__symbol_tstack_start__, &__symbol_tstack_end__) }
+}
+
+/// `.interrupt_table`
+pub fn interrupt_table() -> Region {
+    unsafe {
+        Region::from_symbols(
+            &__symbol_interrupt_table_start__,
+            &__symbol_interrupt_table_end__
+        )
+    }
+}
+
+/// Returns every non-aggregate section with its name, in address order.
+pub fn sections() -> [(&'static str, Region); 12] {
+    [
+        (".text", code()),
+        (".rodata", rodata()),
+        (".data", data()),
+        (".bss", bss()),
+        ("svc stack", svc_stack()),
+        ("irq stack", irq_stack()),
+        ("fiq stack", fiq_stack()),
+        ("abt stack", abt_stack()),
+        ("und stack", und_stack()),
+        ("sys stack", sys_stack()),
+        (".tstack", tstack()),
+        (".interrupt_table", interrupt_table()),
+    ]
+}
+
+/// Panics if a section is malformed, an exception stack is empty, or any two
+/// sections overlap.
+pub fn memmap_check_layout() {
+    let sections = sections();
+    for (i, (_, region)) in sections.iter().enumerate() {
+        if region.start > region.end {
+            panic!("Section ends before it starts!");
+        }
+        if sections[i + 1..].iter().any(|(_, other)| region.overlaps(other)) {
+            panic!("Sections overlap!");
+        }
+    }
+    for stack in [svc_stack(), irq_stack(), fiq_stack(), abt_stack(), und_stack(), sys_stack()] {
+        if stack.is_empty() {
+            panic!("Empty exception stack!");
+        }
+    }
+}
+
+/// Panics if any section extends past the first `ram_size` bytes of memory.
+/// `ram_size` is the size of the ARM memory split reported by the firmware.
+pub fn memmap_check_ram(ram_size: u32) {
+    if sections().iter().any(|(_, region)| region.end > ram_size) {
+        panic!("Kernel does not fit in ARM memory!");
+    }
+}
+
+/// Prints every section's name, bounds and size over the UART.
+/// Precondition: `uart_init()` has been called.
+pub unsafe fn memmap_print() {
+    let mut hex = [0u8; 10];
+    let mut dec = itoa::Buffer::new();
+    uart_print("memory map:\n");
+    for (name, region) in sections() {
+        uart_print("  ");
+        uart_print(name);
+        for _ in name.len()..18 {
+            uart_put8(b' ');
+        }
+        uart_print(u32_as_hex(region.start, &mut hex));
+        uart_print(" - ");
+        uart_print(u32_as_hex(region.end, &mut hex));
+        uart_print("  ");
+        uart_print(dec.format(region.len()));
+        uart_print(" B\n");
+    }
+}
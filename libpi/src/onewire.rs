@@ -0,0 +1,238 @@
+//! Dallas/Maxim 1-Wire master on one pin, with ROM search and DS18B20
+//! temperature reads. The line needs a pull-up (typically 4.7k) and is driven
+//! open-drain like `softi2c`. Time slots are a few microseconds long, so long
+//! running interrupt handlers can corrupt them.
+
+use crate::gpio::*;
+use crate::pin::*;
+use crate::time::*;
+
+const CMD_SEARCH_ROM: u8 = 0xF0;
+const CMD_MATCH_ROM: u8 = 0x55;
+const CMD_SKIP_ROM: u8 = 0xCC;
+
+const DS18B20_CONVERT_T: u8 = 0x44;
+const DS18B20_READ_SCRATCHPAD: u8 = 0xBE;
+
+/// Longest DS18B20 conversion (12-bit resolution) plus some margin.
+const DS18B20_CONVERT_MS: u32 = 1000;
+
+/// A 64-bit device ROM code: family code, serial number and CRC.
+pub type Rom = [u8; 8];
+
+/// Errors reported by 1-Wire transactions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OneWireError {
+    /// No device answered the reset pulse.
+    NoPresence,
+    /// Received data failed its CRC check.
+    Crc,
+    /// The device did not finish in time.
+    Timeout,
+}
+
+/// 1-Wire bus master on one pin.
+pub struct OneWire {
+    pin: Pin<Input>,
+}
+
+/// State of a ROM search over several calls to `OneWire::search_next()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RomSearch {
+    rom: Rom,
+    /// Bit position (1-64) of the last branch taken towards 0; 0 if none.
+    last_discrepancy: u8,
+    done: bool,
+}
+
+impl RomSearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Returns the Dallas/Maxim CRC-8 of `data` (0 if `data` ends in its CRC).
+pub fn onewire_crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut b = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ b) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            b >>= 1;
+        }
+    }
+    crc
+}
+
+impl OneWire {
+    /// Takes the bus pin and releases the line.
+    pub fn new(mut pin: Pin<Input>) -> Self {
+        pin.set_pull(Pull::Up);
+        unsafe { gpio_set_off(pin.number()).unwrap() };
+        Self { pin }
+    }
+
+    /// Returns the pin as an input.
+    pub fn free(self) -> Pin<Input> {
+        self.pin.into_input()
+    }
+
+    fn low(&mut self) {
+        unsafe { gpio_set_output(self.pin.number()).unwrap() };
+    }
+
+    fn release(&mut self) {
+        unsafe { gpio_set_input(self.pin.number()).unwrap() };
+    }
+
+    /// Sends a reset pulse and returns whether any device answered with a
+    /// presence pulse.
+    pub fn reset(&mut self) -> bool {
+        unsafe {
+            self.low();
+            delay_us(480);
+            self.release();
+            delay_us(70);
+            let present = self.pin.is_low();
+            delay_us(410);
+            present
+        }
+    }
+
+    pub fn write_bit(&mut self, b: bool) {
+        unsafe {
+            self.low();
+            if b {
+                delay_us(6);
+                self.release();
+                delay_us(64);
+            } else {
+                delay_us(60);
+                self.release();
+                delay_us(10);
+            }
+        }
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        unsafe {
+            self.low();
+            delay_us(6);
+            self.release();
+            delay_us(9);
+            let b = self.pin.is_high();
+            delay_us(55);
+            b
+        }
+    }
+
+    /// Sends `byte`, LSB first.
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit((byte >> i) & 1 == 1);
+        }
+    }
+
+    /// Receives a byte, LSB first.
+    pub fn read_byte(&mut self) -> u8 {
+        (0..8).fold(0, |byte, i| byte | (self.read_bit() as u8) << i)
+    }
+
+    /// Resets the bus and addresses the device `rom`, or every device if
+    /// `rom` is `None`.
+    pub fn select(&mut self, rom: Option<&Rom>) -> Result<(), OneWireError> {
+        if !self.reset() {
+            return Err(OneWireError::NoPresence);
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(CMD_MATCH_ROM);
+                for &b in rom {
+                    self.write_byte(b);
+                }
+            }
+            None => self.write_byte(CMD_SKIP_ROM),
+        }
+        Ok(())
+    }
+
+    /// Returns the next device ROM of the search in `state`, or `None` once
+    /// every device has been found.
+    pub fn search_next(&mut self, state: &mut RomSearch) -> Result<Option<Rom>, OneWireError> {
+        if state.done {
+            return Ok(None);
+        }
+        if !self.reset() {
+            return Err(OneWireError::NoPresence);
+        }
+        self.write_byte(CMD_SEARCH_ROM);
+
+        let mut last_zero = 0;
+        for n in 1..=64u8 {
+            let (byte, mask) = ((n as usize - 1) / 8, 1 << ((n - 1) % 8));
+            let bit = self.read_bit();
+            let complement = self.read_bit();
+            let dir = match (bit, complement) {
+                // no device left on the bus
+                (true, true) => return Err(OneWireError::NoPresence),
+                (b, c) if b != c => b,
+                // devices differ here: repeat the previous path up to the last
+                // discrepancy, take 1 there and 0 beyond it
+                _ => {
+                    let dir = match n.cmp(&state.last_discrepancy) {
+                        core::cmp::Ordering::Less => state.rom[byte] & mask != 0,
+                        core::cmp::Ordering::Equal => true,
+                        core::cmp::Ordering::Greater => false,
+                    };
+                    if !dir {
+                        last_zero = n;
+                    }
+                    dir
+                }
+            };
+            if dir {
+                state.rom[byte] |= mask;
+            } else {
+                state.rom[byte] &= !mask;
+            }
+            self.write_bit(dir);
+        }
+
+        state.last_discrepancy = last_zero;
+        state.done = last_zero == 0;
+        if onewire_crc8(&state.rom) != 0 {
+            return Err(OneWireError::Crc);
+        }
+        Ok(Some(state.rom))
+    }
+
+    /// Starts a temperature conversion on the DS18B20 `rom` (or the only
+    /// device if `None`), waits for it and returns the temperature in
+    /// 1/16 degrees Celsius. Parasite-powered sensors are not supported.
+    pub fn ds18b20_read_temp(&mut self, rom: Option<&Rom>) -> Result<i16, OneWireError> {
+        self.select(rom)?;
+        self.write_byte(DS18B20_CONVERT_T);
+        // the sensor reads 0 until the conversion is done
+        let timer = unsafe { Timer::set(DS18B20_CONVERT_MS) };
+        while !self.read_bit() {
+            if unsafe { timer.done() } {
+                return Err(OneWireError::Timeout);
+            }
+        }
+
+        self.select(rom)?;
+        self.write_byte(DS18B20_READ_SCRATCHPAD);
+        let mut scratchpad = [0u8; 9];
+        for b in scratchpad.iter_mut() {
+            *b = self.read_byte();
+        }
+        if onewire_crc8(&scratchpad) != 0 {
+            return Err(OneWireError::Crc);
+        }
+        Ok(i16::from_le_bytes([scratchpad[0], scratchpad[1]]))
+    }
+}
@@ -0,0 +1,201 @@
+//! Typed GPIO pins. Each pin is claimed once from the `Gpio` singleton and its
+//! function is tracked in the type (`Pin<Input>`, `Pin<Output>`,
+//! `Pin<Alt<N>>`), so e.g. writing to an input does not compile. The free
+//! functions in `gpio` remain as the unsafe low-level layer; a claimed pin is
+//! always valid, so their `GpioError` cannot occur here.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::gpio::*;
+
+/// Set once `Gpio::take()` has handed out the singleton.
+static GPIO_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Pins currently claimed through `Gpio` (bit `i` = pin `i`).
+static mut CLAIMED: u64 = 0;
+
+/// Pins owned by a driver (e.g. 14/15 by `uart_init`) that cannot be claimed.
+static mut RESERVED: u64 = 0;
+
+/// Errors reported when claiming a pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinError {
+    /// The BCM2835 has no such pin.
+    Invalid(u8),
+    /// The pin is already claimed.
+    Claimed(u8),
+    /// The pin is reserved by a driver.
+    Reserved(u8),
+}
+
+/// Pin mode: input.
+pub struct Input;
+/// Pin mode: output.
+pub struct Output;
+/// Pin mode: alternate function `N` (0-5).
+pub struct Alt<const N: u8>;
+
+/// A claimed GPIO pin in mode `MODE`.
+pub struct Pin<MODE> {
+    pin: u8,
+    _mode: PhantomData<MODE>,
+}
+
+/// Owner of all GPIO pins.
+pub struct Gpio {
+    _private: (),
+}
+
+impl Gpio {
+    /// Returns the GPIO singleton the first time it is called, `None` after.
+    pub fn take() -> Option<Self> {
+        if GPIO_TAKEN.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            Some(Self { _private: () })
+        }
+    }
+
+    /// Claims `pin` and configures it as an input.
+    pub fn claim(&mut self, pin: u8) -> Result<Pin<Input>, PinError> {
+        if gpio_check_pin(pin).is_err() {
+            return Err(PinError::Invalid(pin));
+        }
+        unsafe {
+            if (RESERVED >> pin) & 1 == 1 {
+                return Err(PinError::Reserved(pin));
+            }
+            if (CLAIMED >> pin) & 1 == 1 {
+                return Err(PinError::Claimed(pin));
+            }
+            CLAIMED |= 1 << pin;
+            gpio_set_input(pin).unwrap();
+        }
+        Ok(Pin::new(pin))
+    }
+
+    /// Returns whether `pin` can currently be claimed.
+    pub fn is_free(&self, pin: u8) -> bool {
+        pin < GPIO_PINS && unsafe { ((CLAIMED | RESERVED) >> pin) & 1 == 0 }
+    }
+
+    /// Resets `pin` to an input and makes it available to `claim()` again.
+    pub fn release<MODE>(&mut self, pin: Pin<MODE>) {
+        unsafe {
+            gpio_set_input(pin.pin).unwrap();
+            CLAIMED &= !(1 << pin.pin);
+        }
+    }
+}
+
+/// Marks `pin` as owned by a driver so it cannot be claimed through `Gpio`.
+/// Fails if the application has already claimed it or another driver has
+/// reserved it.
+pub unsafe fn gpio_reserve(pin: u8) -> Result<(), GpioError> {
+    gpio_reserve_all(&[pin])
+}
+
+/// Reserves every pin in `pins` with `gpio_reserve()`, or none of them if one
+/// is invalid, claimed or reserved.
+pub unsafe fn gpio_reserve_all(pins: &[u8]) -> Result<(), GpioError> {
+    for &pin in pins {
+        gpio_check_pin(pin)?;
+        if (CLAIMED >> pin) & 1 == 1 {
+            return Err(GpioError::Claimed(pin));
+        }
+        if (RESERVED >> pin) & 1 == 1 {
+            return Err(GpioError::Reserved(pin));
+        }
+    }
+    for &pin in pins {
+        RESERVED |= 1 << pin;
+    }
+    Ok(())
+}
+
+/// Returns whether a driver has reserved `pin`.
+pub unsafe fn gpio_is_reserved(pin: u8) -> bool {
+    pin < GPIO_PINS && (RESERVED >> pin) & 1 == 1
+}
+
+/// Returns a pin reserved with `gpio_reserve()` to the pool.
+pub unsafe fn gpio_unreserve(pin: u8) {
+    if pin < GPIO_PINS {
+        RESERVED &= !(1 << pin);
+    }
+}
+
+impl<MODE> Pin<MODE> {
+    fn new(pin: u8) -> Self {
+        Self { pin, _mode: PhantomData }
+    }
+
+    /// Returns the BCM pin number.
+    pub fn number(&self) -> u8 {
+        self.pin
+    }
+
+    /// Reconfigures the pin as an input.
+    pub fn into_input(self) -> Pin<Input> {
+        unsafe { gpio_set_input(self.pin).unwrap() };
+        Pin::new(self.pin)
+    }
+
+    /// Reconfigures the pin as an output.
+    pub fn into_output(self) -> Pin<Output> {
+        unsafe { gpio_set_output(self.pin).unwrap() };
+        Pin::new(self.pin)
+    }
+
+    /// Reconfigures the pin to alternate function `N`.
+    /// Precondition: `N < 6`
+    pub fn into_alt<const N: u8>(self) -> Pin<Alt<N>> {
+        const { assert!(N < 6, "BCM2835 has alternate functions 0-5") };
+        // ALT0-3 are 0b100-0b111, ALT4 is 0b011 and ALT5 is 0b010
+        let fsel = match N {
+            0..=3 => 0b100 + N,
+            _ => 7 - N,
+        };
+        unsafe { gpio_set_fn(self.pin, fsel).unwrap() };
+        Pin::new(self.pin)
+    }
+
+    /// Returns whether the pin level is high.
+    pub fn is_high(&self) -> bool {
+        unsafe { gpio_read(self.pin).unwrap() == 1 }
+    }
+
+    /// Returns whether the pin level is low.
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl Pin<Input> {
+    /// Sets the internal pull resistor.
+    pub fn set_pull(&mut self, pull: Pull) {
+        unsafe { gpio_set_pull(self.pin, pull).unwrap() };
+    }
+}
+
+impl Pin<Output> {
+    /// Drives the pin high.
+    pub fn set_high(&mut self) {
+        unsafe { gpio_set_on(self.pin).unwrap() };
+    }
+
+    /// Drives the pin low.
+    pub fn set_low(&mut self) {
+        unsafe { gpio_set_off(self.pin).unwrap() };
+    }
+
+    /// Drives the pin high if `high`, low otherwise.
+    pub fn write(&mut self, high: bool) {
+        if high { self.set_high() } else { self.set_low() }
+    }
+
+    /// Inverts the pin level.
+    pub fn toggle(&mut self) {
+        unsafe { gpio_toggle(self.pin).unwrap() };
+    }
+}
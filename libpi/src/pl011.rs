@@ -0,0 +1,319 @@
+//! PL011 UART0 driver. Unlike the mini-UART it has its own reference clock,
+//! so its baud rate does not follow the core clock, and it supports parity,
+//! two stop bits, RTS/CTS flow control and per-byte error flags. TXD0/RXD0
+//! are GPIO 14/15 (shared with the mini-UART) and CTS0/RTS0 are GPIO 16/17.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::gpio::*;
+use crate::pin::*;
+use crate::time::*;
+use crate::mailbox::*;
+use crate::interrupts::*;
+use crate::serial::*;
+
+const PL011_BASE: u32 = 0x20201000;
+const PL011_DR: u32 = PL011_BASE;
+const PL011_RSRECR: u32 = PL011_BASE + 0x04;
+const PL011_FR: u32 = PL011_BASE + 0x18;
+const PL011_IBRD: u32 = PL011_BASE + 0x24;
+const PL011_FBRD: u32 = PL011_BASE + 0x28;
+const PL011_LCRH: u32 = PL011_BASE + 0x2C;
+const PL011_CR: u32 = PL011_BASE + 0x30;
+const PL011_IFLS: u32 = PL011_BASE + 0x34;
+const PL011_IMSC: u32 = PL011_BASE + 0x38;
+const PL011_MIS: u32 = PL011_BASE + 0x40;
+const PL011_ICR: u32 = PL011_BASE + 0x44;
+
+// PL011_FR bits
+const FR_BUSY: u8 = 3;
+const FR_RXFE: u8 = 4;
+const FR_TXFF: u8 = 5;
+
+// PL011_DR error bits
+const DR_FE: u8 = 8;
+const DR_PE: u8 = 9;
+const DR_BE: u8 = 10;
+const DR_OE: u8 = 11;
+
+// PL011_LCRH bits
+const LCRH_PEN: u8 = 1;
+const LCRH_EPS: u8 = 2;
+const LCRH_STP2: u8 = 3;
+const LCRH_FEN: u8 = 4;
+
+// PL011_CR bits
+const CR_UARTEN: u8 = 0;
+const CR_TXE: u8 = 8;
+const CR_RXE: u8 = 9;
+const CR_RTSEN: u8 = 14;
+const CR_CTSEN: u8 = 15;
+
+// PL011_IMSC / PL011_MIS bits
+const INT_RX: u8 = 4;
+const INT_RT: u8 = 6;
+
+/// Reference clock assumed if the firmware does not report one.
+const DEFAULT_UART_CLOCK: u32 = 48_000_000;
+
+const RX_BUF_LEN: usize = 256;
+
+/// Line settings applied by `pl011_init()`.
+#[derive(Clone, Copy, Debug)]
+pub struct Pl011Config {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// Use RTS/CTS hardware flow control on GPIO 16/17.
+    pub flow_control: bool,
+}
+
+impl Pl011Config {
+    /// 115200 8N1 without flow control.
+    pub const DEFAULT: Self = Self {
+        baud: 115200,
+        data_bits: DataBits::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        flow_control: false,
+    };
+}
+
+impl Default for Pl011Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Bytes received by the interrupt handler, with their `PL011_DR` error bits.
+static mut RX_BUF: [u16; RX_BUF_LEN] = [0; RX_BUF_LEN];
+static mut RX_HEAD: usize = 0;
+static mut RX_LEN: usize = 0;
+/// Bytes dropped because `RX_BUF` was full.
+static mut RX_DROPPED: u32 = 0;
+static mut RX_IRQ: bool = false;
+static mut FLOW_CONTROL: bool = false;
+
+/// Routes GPIO 14/15 (and 16/17 with flow control) to UART0 and configures it
+/// with `cfg`. Returns the actual baud rate, or an error if one of the pins is
+/// claimed or in use by another driver, e.g. the mini-UART (see
+/// `uart_disable()`).
+pub unsafe fn pl011_init(cfg: &Pl011Config) -> Result<u32, GpioError> {
+    let pins: &[u8] = if cfg.flow_control { &[14, 15, 16, 17] } else { &[14, 15] };
+    gpio_reserve_all(pins)?;
+    pl011_stop();
+    gpio_set_fn(14, 0b100).unwrap(); // TXD0 (ALT0)
+    gpio_set_fn(15, 0b100).unwrap(); // RXD0 (ALT0)
+    FLOW_CONTROL = cfg.flow_control;
+    if cfg.flow_control {
+        gpio_set_fn(16, 0b111).unwrap(); // CTS0 (ALT3)
+        gpio_set_fn(17, 0b111).unwrap(); // RTS0 (ALT3)
+    }
+
+    put32(PL011_ICR, 0x7FF);
+    let baud = pl011_set_baud(cfg.baud);
+    let mut lcrh = bits_modify(1 << LCRH_FEN, 6, 5, cfg.data_bits as u32 - 5);
+    lcrh |= match cfg.parity {
+        Parity::None => 0,
+        Parity::Even => 1 << LCRH_PEN | 1 << LCRH_EPS,
+        Parity::Odd => 1 << LCRH_PEN,
+    };
+    if cfg.stop_bits == StopBits::Two {
+        lcrh = bit_set(lcrh, LCRH_STP2);
+    }
+    put32(PL011_LCRH, lcrh);
+
+    let mut cr = 1 << CR_UARTEN | 1 << CR_TXE | 1 << CR_RXE;
+    if cfg.flow_control {
+        cr |= 1 << CR_RTSEN | 1 << CR_CTSEN;
+    }
+    put32(PL011_CR, cr);
+    dsb();
+    Ok(baud)
+}
+
+/// Waits for pending output, disables the UART and frees its pins.
+pub unsafe fn pl011_disable() {
+    pl011_flush_tx();
+    pl011_stop();
+    for pin in [14, 15] {
+        gpio_set_input(pin).unwrap();
+        gpio_unreserve(pin);
+    }
+    if FLOW_CONTROL {
+        for pin in [16, 17] {
+            gpio_set_input(pin).unwrap();
+            gpio_unreserve(pin);
+        }
+        FLOW_CONTROL = false;
+    }
+}
+
+/// Disables the UART and its interrupt and flushes the FIFOs.
+unsafe fn pl011_stop() {
+    pl011_rx_irq_disable();
+    put32(PL011_CR, 0);
+    put32(PL011_LCRH, 0); // clearing FEN flushes the FIFOs
+    dsb();
+}
+
+/// Sets the integer and fractional divisors for `baud` from the UART
+/// reference clock and returns the actual baud rate.
+/// Precondition: the UART is disabled.
+unsafe fn pl011_set_baud(baud: u32) -> u32 {
+    let clock = mbox_clock_rate(Clock::Uart).unwrap_or(DEFAULT_UART_CLOCK);
+    // divisor = clock / (16 * baud) in 1/64ths, rounded to nearest
+    let div = ((clock as u64 * 4 + baud as u64 / 2) / baud.max(1) as u64).clamp(64, 0xFFFF << 6);
+    put32(PL011_IBRD, (div >> 6) as u32);
+    put32(PL011_FBRD, (div & 63) as u32);
+    (clock as u64 * 4 / div) as u32
+}
+
+/// Puts one byte on the TX FIFO. If necessary, waits until the FIFO has space
+/// (and, with flow control, until the receiver asserts CTS).
+pub unsafe fn pl011_put8(x: u8) {
+    while bit_is_set(get32(PL011_FR), FR_TXFF) {
+        wait();
+    }
+    put32(PL011_DR, x as u32);
+}
+
+/// Writes a string to the UART, sending it character by character.
+pub unsafe fn pl011_print(msg: &str) {
+    for byte in msg.bytes() {
+        pl011_put8(byte);
+    }
+}
+
+/// Returns whether there is at least one received byte.
+#[allow(static_mut_refs)]
+pub unsafe fn pl011_has_data() -> bool {
+    if RX_IRQ {
+        RX_LEN > 0
+    } else {
+        !bit_is_set(get32(PL011_FR), FR_RXFE)
+    }
+}
+
+/// Returns the next received byte, or the error flagged on it, if there is
+/// one. Takes bytes from the interrupt buffer when RX interrupts are enabled.
+#[allow(static_mut_refs)]
+pub unsafe fn pl011_get8_async() -> Option<Result<u8, SerialError>> {
+    let dr = if RX_IRQ {
+        // keep the handler from appending while the buffer is updated
+        irq_disable(UART0_IRQ);
+        let dr = (RX_LEN > 0).then(|| {
+            let dr = RX_BUF[RX_HEAD];
+            RX_HEAD = (RX_HEAD + 1) % RX_BUF_LEN;
+            RX_LEN -= 1;
+            dr
+        });
+        irq_enable(UART0_IRQ);
+        dr? as u32
+    } else {
+        if bit_is_set(get32(PL011_FR), FR_RXFE) {
+            return None;
+        }
+        get32(PL011_DR)
+    };
+    Some(pl011_check(dr))
+}
+
+/// Waits for the next received byte.
+pub unsafe fn pl011_get8() -> Result<u8, SerialError> {
+    loop {
+        if let Some(r) = pl011_get8_async() {
+            return r;
+        }
+        wait();
+    }
+}
+
+/// Converts a `PL011_DR` value into its byte or its most serious error.
+fn pl011_check(dr: u32) -> Result<u8, SerialError> {
+    if bit_is_set(dr, DR_OE) {
+        Err(SerialError::Overrun)
+    } else if bit_is_set(dr, DR_BE) {
+        Err(SerialError::Break)
+    } else if bit_is_set(dr, DR_FE) {
+        Err(SerialError::Framing)
+    } else if bit_is_set(dr, DR_PE) {
+        Err(SerialError::Parity)
+    } else {
+        Ok(dr as u8)
+    }
+}
+
+/// Returns only when the TX FIFO is empty and the last byte has been sent.
+pub unsafe fn pl011_flush_tx() {
+    while bit_is_set(get32(PL011_FR), FR_BUSY) {
+        wait();
+    }
+}
+
+/// Returns the number of received bytes dropped because the interrupt buffer
+/// was full.
+pub unsafe fn pl011_rx_dropped() -> u32 {
+    RX_DROPPED
+}
+
+/// Receives into a buffer from the UART interrupt, so input is not lost while
+/// the program is busy. `pl011_get8()` then reads from the buffer.
+/// Precondition: interrupts are set up with `interrupt_init()`.
+pub unsafe fn pl011_rx_irq_enable() {
+    RX_IRQ = true;
+    // interrupt at 1/2 full RX FIFO, or when received data sits idle
+    put32(PL011_IFLS, bits_modify(get32(PL011_IFLS), 5, 3, 0b010));
+    put32(PL011_IMSC, 1 << INT_RX | 1 << INT_RT);
+    dsb();
+    irq_enable(UART0_IRQ);
+}
+
+/// Returns to polled reception. Bytes already buffered are discarded.
+pub unsafe fn pl011_rx_irq_disable() {
+    irq_disable(UART0_IRQ);
+    put32(PL011_IMSC, 0);
+    dsb();
+    RX_IRQ = false;
+    RX_HEAD = 0;
+    RX_LEN = 0;
+}
+
+/// Moves received bytes from the FIFO into the buffer. Called from
+/// `interrupt_vector`.
+pub unsafe fn pl011_irq_handler() {
+    if get32(PL011_MIS) & (1 << INT_RX | 1 << INT_RT) == 0 {
+        return;
+    }
+    while !bit_is_set(get32(PL011_FR), FR_RXFE) {
+        let dr = get32(PL011_DR);
+        if RX_LEN == RX_BUF_LEN {
+            RX_DROPPED += 1;
+            continue;
+        }
+        RX_BUF[(RX_HEAD + RX_LEN) % RX_BUF_LEN] = dr as u16;
+        RX_LEN += 1;
+    }
+    put32(PL011_RSRECR, 0); // clear the sticky error flags
+    put32(PL011_ICR, 1 << INT_RX | 1 << INT_RT);
+    dsb();
+}
+
+/// Handle implementing `SerialPort` for UART0.
+/// Precondition for use: `pl011_init()` has been called.
+pub struct Pl011;
+
+impl SerialPort for Pl011 {
+    fn write_byte(&mut self, byte: u8) {
+        unsafe { pl011_put8(byte) };
+    }
+
+    fn try_read_byte(&mut self) -> Option<Result<u8, SerialError>> {
+        unsafe { pl011_get8_async() }
+    }
+
+    fn flush(&mut self) {
+        unsafe { pl011_flush_tx() };
+    }
+}
@@ -0,0 +1,202 @@
+//! Hardware PWM driver for the two BCM2835 PWM channels.
+
+use crate::io::*;
+use crate::bits::*;
+use crate::gpio::*;
+use crate::pin::*;
+use crate::clock::*;
+use crate::time::*;
+
+const PWM_BASE: u32 = 0x2020C000;
+const PWM_CTL: u32 = PWM_BASE;
+const PWM_STA: u32 = PWM_BASE + 0x04;
+const PWM_RNG1: u32 = PWM_BASE + 0x10;
+const PWM_DAT1: u32 = PWM_BASE + 0x14;
+const PWM_RNG2: u32 = PWM_BASE + 0x20;
+const PWM_DAT2: u32 = PWM_BASE + 0x24;
+
+// PWM_CTL bits of channel 0; channel 1 uses the same bits shifted by 8.
+const PWM_PWEN: u8 = 0;
+const PWM_POLA: u8 = 4;
+const PWM_MSEN: u8 = 7;
+
+/// Servo frames are 20 ms long.
+const SERVO_FRAME_US: u32 = 20_000;
+
+/// PWM clock used by the servo helper: one tick per microsecond.
+const SERVO_CLOCK_HZ: u32 = 1_000_000;
+
+/// PWM output channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwmChannel {
+    /// PWM0 on GPIO 12 (ALT0) or 18 (ALT5)
+    Pwm0 = 0,
+    /// PWM1 on GPIO 13 (ALT0) or 19 (ALT5)
+    Pwm1 = 1,
+}
+
+impl PwmChannel {
+    /// Returns the range and data registers of the channel.
+    fn registers(self) -> (u32, u32) {
+        match self {
+            PwmChannel::Pwm0 => (PWM_RNG1, PWM_DAT1),
+            PwmChannel::Pwm1 => (PWM_RNG2, PWM_DAT2),
+        }
+    }
+
+    /// Returns `bit` of channel 0 moved to this channel.
+    fn ctl_bit(self, bit: u8) -> u8 {
+        bit + 8 * self as u8
+    }
+}
+
+/// PWM output algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwmMode {
+    /// High for `data` ticks, then low for `range - data` ticks.
+    MarkSpace,
+    /// `data` high ticks spread as evenly as possible over `range` ticks.
+    Balanced,
+}
+
+/// Errors reported by the PWM driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwmError {
+    /// The pin cannot be routed to a PWM channel.
+    NotPwmPin(u8),
+    Clock(ClockError),
+    Gpio(GpioError),
+}
+
+impl From<ClockError> for PwmError {
+    fn from(e: ClockError) -> Self {
+        PwmError::Clock(e)
+    }
+}
+
+impl From<GpioError> for PwmError {
+    fn from(e: GpioError) -> Self {
+        PwmError::Gpio(e)
+    }
+}
+
+/// Pin routed to each channel by `pwm_init()`.
+static mut PWM_PINS: [Option<u8>; 2] = [None; 2];
+
+/// Returns the channel and alternate function select value that route `pin`
+/// to PWM.
+fn pwm_pin(pin: u8) -> Result<(PwmChannel, u8), PwmError> {
+    match pin {
+        12 => Ok((PwmChannel::Pwm0, 0b100)), // ALT0
+        13 => Ok((PwmChannel::Pwm1, 0b100)), // ALT0
+        18 => Ok((PwmChannel::Pwm0, 0b010)), // ALT5
+        19 => Ok((PwmChannel::Pwm1, 0b010)), // ALT5
+        _ => Err(PwmError::NotPwmPin(pin)),
+    }
+}
+
+/// Sets the PWM clock shared by both channels to approximately `hz` and
+/// returns the actual frequency. Running channels are paused meanwhile.
+pub unsafe fn pwm_clock_init(hz: u32) -> Result<u32, PwmError> {
+    // prefer the oscillator when it divides down exactly
+    let source = match ClockSource::Oscillator.hz() {
+        Some(osc) if hz != 0 && osc % hz == 0 => ClockSource::Oscillator,
+        _ => ClockSource::PllD,
+    };
+    let ctl = get32(PWM_CTL);
+    put32(PWM_CTL, 0);
+    dsb();
+    let actual = clock_set_hz(ClockId::Pwm, source, hz)?;
+    delay_cycles(150); // let the new clock settle before re-enabling
+    put32(PWM_CTL, ctl);
+    dsb();
+    Ok(actual)
+}
+
+/// Routes `pin` to its PWM channel, releasing the pin the channel used
+/// before, and enables the channel in `mode` with `range` ticks per period
+/// and an initial duty of 0. Returns the channel, or an error if `pin` is
+/// claimed or in use by another driver.
+/// Precondition: the PWM clock has been started with `pwm_clock_init()`.
+pub unsafe fn pwm_init(pin: u8, mode: PwmMode, range: u32) -> Result<PwmChannel, PwmError> {
+    let (channel, alt) = pwm_pin(pin)?;
+    if PWM_PINS[channel as usize] != Some(pin) {
+        gpio_reserve(pin)?;
+        pwm_disable(channel);
+        PWM_PINS[channel as usize] = Some(pin);
+    }
+    pwm_stop(channel);
+    gpio_set_fn(pin, alt).unwrap();
+    pwm_set_range(channel, range);
+    pwm_set_data(channel, 0);
+    let mut ctl = get32(PWM_CTL);
+    ctl = match mode {
+        PwmMode::MarkSpace => bit_set(ctl, channel.ctl_bit(PWM_MSEN)),
+        PwmMode::Balanced => bit_clr(ctl, channel.ctl_bit(PWM_MSEN)),
+    };
+    put32(PWM_CTL, bit_set(ctl, channel.ctl_bit(PWM_PWEN)));
+    dsb();
+    Ok(channel)
+}
+
+/// Stops `channel` and returns its pin to an input.
+pub unsafe fn pwm_disable(channel: PwmChannel) {
+    pwm_stop(channel);
+    if let Some(pin) = PWM_PINS[channel as usize].take() {
+        gpio_set_input(pin).unwrap();
+        gpio_unreserve(pin);
+    }
+}
+
+/// Stops `channel`; its output idles low.
+unsafe fn pwm_stop(channel: PwmChannel) {
+    put32(PWM_CTL, bit_clr(get32(PWM_CTL), channel.ctl_bit(PWM_PWEN)));
+    dsb();
+}
+
+/// Inverts the output of `channel` if `inverted`.
+pub unsafe fn pwm_set_polarity(channel: PwmChannel, inverted: bool) {
+    let bit = channel.ctl_bit(PWM_POLA);
+    let ctl = get32(PWM_CTL);
+    put32(PWM_CTL, if inverted { bit_set(ctl, bit) } else { bit_clr(ctl, bit) });
+    dsb();
+}
+
+/// Sets the period of `channel` to `range` clock ticks.
+pub unsafe fn pwm_set_range(channel: PwmChannel, range: u32) {
+    put32(channel.registers().0, range);
+    dsb();
+}
+
+/// Sets the number of high ticks per period of `channel` to `data`.
+pub unsafe fn pwm_set_data(channel: PwmChannel, data: u32) {
+    put32(channel.registers().1, data);
+    dsb();
+}
+
+/// Sets the duty cycle of `channel` to `permille` / 1000 of its range.
+/// Precondition: `permille <= 1000`
+pub unsafe fn pwm_set_duty(channel: PwmChannel, permille: u32) {
+    assert!(permille <= 1000);
+    let range = get32(channel.registers().0);
+    pwm_set_data(channel, (range as u64 * permille as u64 / 1000) as u32);
+}
+
+/// Returns the raw PWM status register (FIFO and error flags).
+pub unsafe fn pwm_status() -> u32 {
+    get32(PWM_STA)
+}
+
+/// Sets up `pin` to drive a hobby servo: 1 MHz PWM clock, mark-space mode and
+/// 20 ms frames. Note that the clock is shared with the other channel.
+pub unsafe fn pwm_servo_init(pin: u8) -> Result<PwmChannel, PwmError> {
+    pwm_clock_init(SERVO_CLOCK_HZ)?;
+    pwm_init(pin, PwmMode::MarkSpace, SERVO_FRAME_US)
+}
+
+/// Sets the servo pulse width on `channel` to `us` microseconds (typically
+/// 1000-2000).
+/// Precondition: `channel` was set up with `pwm_servo_init()`.
+pub unsafe fn pwm_servo_set_us(channel: PwmChannel, us: u32) {
+    pwm_set_data(channel, us.min(SERVO_FRAME_US));
+}
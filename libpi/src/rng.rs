@@ -0,0 +1,159 @@
+//! Hardware random number generator, with a seeded software generator as a
+//! fallback for emulators that do not model the peripheral. `rng_init()`
+//! picks the source; `Rng` then implements `rand_core::RngCore` over it.
+
+use crate::io::*;
+use crate::time::*;
+
+const RNG_BASE: u32 = 0x20104000;
+const RNG_CTRL: u32 = RNG_BASE;
+const RNG_STATUS: u32 = RNG_BASE + 0x04;
+const RNG_DATA: u32 = RNG_BASE + 0x08;
+const RNG_INT_MASK: u32 = RNG_BASE + 0x10;
+
+/// Number of initial words the generator discards while its entropy source
+/// settles.
+const RNG_WARMUP_COUNT: u32 = 0x40000;
+
+/// How long to wait for the first word before falling back to the PRNG.
+const RNG_TIMEOUT_MS: u32 = 1000;
+
+/// Seed of the fallback PRNG unless `rng_init_seeded()` sets one.
+const DEFAULT_SEED: u64 = 0x2545F4914F6CDD1D;
+
+/// Where random numbers come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngSource {
+    /// The hardware generator.
+    Hardware,
+    /// The seeded software generator: deterministic, not for secrets.
+    Prng,
+}
+
+/// Deterministic software generator (SplitMix64).
+#[derive(Clone, Copy, Debug)]
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl rand_core::RngCore for Prng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        Prng::next_u64(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+static mut SOURCE: Option<RngSource> = None;
+static mut PRNG: Prng = Prng::new(DEFAULT_SEED);
+
+/// Starts the hardware generator and waits for it to warm up. Falls back to
+/// the PRNG if no data arrives within `RNG_TIMEOUT_MS`. Returns the source
+/// in use.
+pub unsafe fn rng_init() -> RngSource {
+    put32(RNG_INT_MASK, 1); // no interrupts
+    put32(RNG_STATUS, RNG_WARMUP_COUNT);
+    put32(RNG_CTRL, 1);
+    dsb();
+    let timer = Timer::set(RNG_TIMEOUT_MS);
+    let source = loop {
+        if rng_words_available() > 0 {
+            break RngSource::Hardware;
+        }
+        if timer.done() {
+            put32(RNG_CTRL, 0);
+            break RngSource::Prng;
+        }
+    };
+    SOURCE = Some(source);
+    source
+}
+
+/// Uses the PRNG seeded with `seed`, e.g. for reproducible test runs.
+pub unsafe fn rng_init_seeded(seed: u64) {
+    PRNG = Prng::new(seed);
+    SOURCE = Some(RngSource::Prng);
+}
+
+/// Returns the source in use, or `None` before `rng_init()`.
+pub unsafe fn rng_source() -> Option<RngSource> {
+    SOURCE
+}
+
+/// Returns the number of words waiting in the hardware FIFO.
+unsafe fn rng_words_available() -> u32 {
+    get32(RNG_STATUS) >> 24
+}
+
+/// Returns a random word, waiting for the hardware if necessary.
+/// Precondition: `rng_init()` or `rng_init_seeded()` has been called.
+#[allow(static_mut_refs)]
+pub unsafe fn rng_next_u32() -> u32 {
+    match SOURCE.expect("rng_init() not called") {
+        RngSource::Hardware => {
+            while rng_words_available() == 0 {
+                wait();
+            }
+            get32(RNG_DATA)
+        }
+        RngSource::Prng => (PRNG.next_u64() >> 32) as u32,
+    }
+}
+
+/// Fills `buf` with random bytes.
+/// Precondition: `rng_init()` or `rng_init_seeded()` has been called.
+pub unsafe fn rng_fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(4) {
+        let word = rng_next_u32().to_le_bytes();
+        chunk.copy_from_slice(&word[..chunk.len()]);
+    }
+}
+
+/// Handle implementing `rand_core::RngCore` over the source chosen by
+/// `rng_init()`.
+/// Precondition for use: `rng_init()` or `rng_init_seeded()` has been called.
+pub struct Rng;
+
+impl rand_core::RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        unsafe { rng_next_u32() }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        unsafe { rng_fill_bytes(dest) }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
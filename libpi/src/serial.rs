@@ -0,0 +1,70 @@
+//! Interface common to the UARTs, so application code can run on either the
+//! mini-UART (`MiniUart`) or the PL011 (`Pl011`).
+
+/// Errors flagged on a received byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// The byte had no valid stop bit.
+    Framing,
+    Parity,
+    /// The line was held low for longer than a whole frame.
+    Break,
+    /// Data arrived while the receive FIFO was full and was lost.
+    Overrun,
+}
+
+/// Number of data bits per frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Five = 5,
+    Six = 6,
+    Seven = 7,
+    Eight = 8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// A UART that has been initialized by its driver.
+pub trait SerialPort {
+    /// Queues `byte` for sending, waiting for FIFO space if necessary.
+    fn write_byte(&mut self, byte: u8);
+
+    /// Returns the next received byte if there is one, or the error flagged
+    /// on it.
+    fn try_read_byte(&mut self) -> Option<Result<u8, SerialError>>;
+
+    /// Returns only once every queued byte has been sent.
+    fn flush(&mut self);
+
+    /// Waits for the next received byte.
+    fn read_byte(&mut self) -> Result<u8, SerialError> {
+        loop {
+            if let Some(r) = self.try_read_byte() {
+                return r;
+            }
+        }
+    }
+
+    /// Sends every byte of `buf`.
+    fn write_all(&mut self, buf: &[u8]) {
+        for &b in buf {
+            self.write_byte(b);
+        }
+    }
+
+    /// Sends `msg` byte by byte.
+    fn print(&mut self, msg: &str) {
+        self.write_all(msg.as_bytes());
+    }
+}
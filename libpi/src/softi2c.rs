@@ -0,0 +1,200 @@
+//! Bit-banged I2C master on arbitrary pins. The lines are driven open-drain:
+//! the output latch stays low and a line is pulled low by making the pin an
+//! output and released by making it an input. Implements
+//! `embedded_hal::i2c::I2c` with the same errors as the hardware driver.
+
+use crate::gpio::*;
+use crate::pin::*;
+use crate::i2c::{I2cConfig, I2cError};
+use crate::time::*;
+
+/// I2C master on `sda`/`scl`, which need pull-ups (the internal ones are
+/// enabled, but are too weak for fast buses).
+pub struct SoftI2c {
+    sda: Pin<Input>,
+    scl: Pin<Input>,
+    /// Half a clock period in microseconds.
+    half_us: u32,
+    /// How long a device may hold SCL low, in microseconds; 0 waits forever.
+    stretch_us: u32,
+}
+
+impl SoftI2c {
+    /// Takes the bus pins and releases both lines. `cfg.hz` is an upper
+    /// bound, in whole microsecond steps.
+    pub fn new(mut sda: Pin<Input>, mut scl: Pin<Input>, cfg: &I2cConfig) -> Self {
+        sda.set_pull(Pull::Up);
+        scl.set_pull(Pull::Up);
+        unsafe {
+            gpio_set_off(sda.number()).unwrap();
+            gpio_set_off(scl.number()).unwrap();
+        }
+        let hz = cfg.hz.max(1);
+        Self {
+            sda,
+            scl,
+            half_us: 500_000 / hz,
+            stretch_us: (cfg.clock_stretch_timeout as u64 * 1_000_000 / hz as u64) as u32,
+        }
+    }
+
+    /// Returns the pins as inputs.
+    pub fn free(self) -> (Pin<Input>, Pin<Input>) {
+        (self.sda.into_input(), self.scl.into_input())
+    }
+
+    fn half(&self) {
+        unsafe { delay_us(self.half_us) };
+    }
+
+    /// Pulls `pin` low or releases it.
+    fn line(pin: &Pin<Input>, high: bool) {
+        unsafe {
+            if high {
+                gpio_set_input(pin.number()).unwrap();
+            } else {
+                gpio_set_output(pin.number()).unwrap();
+            }
+        }
+    }
+
+    fn sda(&mut self, high: bool) {
+        Self::line(&self.sda, high);
+    }
+
+    fn scl_low(&mut self) {
+        Self::line(&self.scl, false);
+    }
+
+    /// Releases SCL and waits while a device stretches the clock.
+    fn scl_high(&mut self) -> Result<(), I2cError> {
+        Self::line(&self.scl, true);
+        let start = unsafe { timer_get_usec() };
+        while self.scl.is_low() {
+            let waited = unsafe { timer_get_usec() }.wrapping_sub(start);
+            if self.stretch_us != 0 && waited > self.stretch_us {
+                return Err(I2cError::ClockStretchTimeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a start, or a repeated start if the bus is already ours.
+    fn start(&mut self) -> Result<(), I2cError> {
+        self.sda(true);
+        self.half();
+        self.scl_high()?;
+        self.half();
+        self.sda(false);
+        self.half();
+        self.scl_low();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), I2cError> {
+        self.sda(false);
+        self.half();
+        self.scl_high()?;
+        self.half();
+        self.sda(true);
+        self.half();
+        Ok(())
+    }
+
+    /// Clocks out one bit.
+    fn write_bit(&mut self, b: bool) -> Result<(), I2cError> {
+        self.sda(b);
+        self.half();
+        self.scl_high()?;
+        self.half();
+        self.scl_low();
+        Ok(())
+    }
+
+    /// Clocks in one bit.
+    fn read_bit(&mut self) -> Result<bool, I2cError> {
+        self.sda(true);
+        self.half();
+        self.scl_high()?;
+        let b = self.sda.is_high();
+        self.half();
+        self.scl_low();
+        Ok(b)
+    }
+
+    /// Sends `byte` and fails unless the device acknowledges it.
+    fn write_byte(&mut self, byte: u8) -> Result<(), I2cError> {
+        for bit in (0..8).rev() {
+            self.write_bit((byte >> bit) & 1 == 1)?;
+        }
+        if self.read_bit()? { Err(I2cError::Nack) } else { Ok(()) }
+    }
+
+    /// Receives a byte, then acknowledges it if `ack`.
+    fn read_byte(&mut self, ack: bool) -> Result<u8, I2cError> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = byte << 1 | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    /// Runs `operations` between one start and one stop, with a repeated
+    /// start whenever the direction changes.
+    fn run(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>]
+    ) -> Result<(), I2cError> {
+        use embedded_hal::i2c::Operation;
+        if address > 0x7F {
+            return Err(I2cError::InvalidAddress(address));
+        }
+        let mut reading = None;
+        for i in 0..operations.len() {
+            let next_is_read = matches!(operations.get(i + 1), Some(Operation::Read(_)));
+            match &mut operations[i] {
+                Operation::Write(data) => {
+                    if reading != Some(false) {
+                        self.start()?;
+                        self.write_byte(address << 1)?;
+                        reading = Some(false);
+                    }
+                    for &b in data.iter() {
+                        self.write_byte(b)?;
+                    }
+                }
+                Operation::Read(buf) => {
+                    if reading != Some(true) {
+                        self.start()?;
+                        self.write_byte(address << 1 | 1)?;
+                        reading = Some(true);
+                    }
+                    // the master NACKs the last byte it reads
+                    let len = buf.len();
+                    for (j, b) in buf.iter_mut().enumerate() {
+                        *b = self.read_byte(j + 1 < len || next_is_read)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal::i2c::ErrorType for SoftI2c {
+    type Error = I2cError;
+}
+
+impl embedded_hal::i2c::I2c for SoftI2c {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>]
+    ) -> Result<(), I2cError> {
+        let result = self.run(address, operations);
+        let stopped = self.stop();
+        result.and(stopped)
+    }
+}
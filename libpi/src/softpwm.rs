@@ -0,0 +1,141 @@
+//! Software PWM on arbitrary output pins, driven by the ARM timer interrupt.
+//! Every period is split into `steps` ticks; all channels go high together at
+//! tick 0 and each goes low at the tick matching its duty. The pins that
+//! change in a tick are switched with one `gpio_write_mask()`, so channels in
+//! the same bank switch together.
+
+use crate::gpio::*;
+use crate::armtimer::*;
+
+/// Number of software PWM channels.
+pub const SOFTPWM_CHANNELS: usize = 8;
+
+/// Shortest tick; faster interrupts leave little time for anything else.
+const MIN_TICK_US: u32 = 20;
+
+/// Errors reported by the software PWM engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoftPwmError {
+    /// `freq_hz * steps` needs a tick shorter than the engine supports.
+    InvalidFrequency,
+    /// All channels are in use.
+    NoFreeChannel,
+    Gpio(GpioError),
+}
+
+impl From<GpioError> for SoftPwmError {
+    fn from(e: GpioError) -> Self {
+        SoftPwmError::Gpio(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    pin: u8,
+    /// High ticks per period of the current period.
+    data: u32,
+    /// Takes effect at the start of the next period, so a change never cuts
+    /// a pulse short.
+    next_data: u32,
+}
+
+static mut CHANNELS: [Channel; SOFTPWM_CHANNELS] =
+    [Channel { pin: 0, data: 0, next_data: 0 }; SOFTPWM_CHANNELS];
+
+/// Channels in use (bit `i` = channel `i`). The tick only reads channels set
+/// here, so a channel is filled in before its bit is set.
+static mut ACTIVE: u32 = 0;
+
+static mut STEPS: u32 = 0;
+static mut STEP: u32 = 0;
+
+/// Starts (or retimes) the engine at about `freq_hz` periods per second with
+/// `steps` duty levels, and returns the actual frequency. Existing channels
+/// keep their data, which is in units of `steps`.
+/// Precondition: interrupts are set up with `interrupt_init()`.
+pub unsafe fn softpwm_init(freq_hz: u32, steps: u32) -> Result<u32, SoftPwmError> {
+    let ticks_per_sec = freq_hz.checked_mul(steps).ok_or(SoftPwmError::InvalidFrequency)?;
+    if ticks_per_sec == 0 || 1_000_000 / ticks_per_sec < MIN_TICK_US {
+        return Err(SoftPwmError::InvalidFrequency);
+    }
+    let tick_us = 1_000_000 / ticks_per_sec;
+    arm_timer_stop();
+    STEPS = steps;
+    STEP = 0;
+    arm_timer_start(tick_us, softpwm_tick);
+    Ok(1_000_000 / (tick_us * steps))
+}
+
+/// Stops the engine and drives every channel low. Channels stay allocated.
+#[allow(static_mut_refs)]
+pub unsafe fn softpwm_stop() {
+    arm_timer_stop();
+    let mut clr = 0u64;
+    for (i, ch) in CHANNELS.iter().enumerate() {
+        if (ACTIVE >> i) & 1 == 1 {
+            clr |= 1 << ch.pin;
+        }
+    }
+    gpio_write_mask(0, clr).unwrap();
+}
+
+/// Configures `pin` as a low output and adds it as a channel with `data`
+/// high ticks per period. Returns the channel number.
+#[allow(static_mut_refs)]
+pub unsafe fn softpwm_add(pin: u8, data: u32) -> Result<usize, SoftPwmError> {
+    let Some(i) = (0..SOFTPWM_CHANNELS).find(|i| (ACTIVE >> i) & 1 == 0) else {
+        return Err(SoftPwmError::NoFreeChannel);
+    };
+    gpio_set_output(pin)?;
+    gpio_set_off(pin)?;
+    CHANNELS[i] = Channel { pin, data: 0, next_data: data };
+    ACTIVE |= 1 << i;
+    Ok(i)
+}
+
+/// Removes `channel` and drives its pin low. The pin stays an output.
+pub unsafe fn softpwm_remove(channel: usize) {
+    assert!(channel < SOFTPWM_CHANNELS);
+    ACTIVE &= !(1 << channel);
+    gpio_set_off(CHANNELS[channel].pin).unwrap();
+}
+
+/// Sets `channel` to `data` high ticks per period from the next period on.
+/// `data >= steps` keeps the pin high.
+pub unsafe fn softpwm_set_data(channel: usize, data: u32) {
+    assert!(channel < SOFTPWM_CHANNELS);
+    CHANNELS[channel].next_data = data;
+}
+
+/// Sets the duty cycle of `channel` to `permille` / 1000.
+/// Precondition: `permille <= 1000`
+pub unsafe fn softpwm_set_duty(channel: usize, permille: u32) {
+    assert!(permille <= 1000);
+    softpwm_set_data(channel, (STEPS as u64 * permille as u64 / 1000) as u32);
+}
+
+/// Timer callback: switches every channel that changes level in this tick.
+#[allow(static_mut_refs)]
+unsafe fn softpwm_tick() {
+    let mut set = 0u64;
+    let mut clr = 0u64;
+    for (i, ch) in CHANNELS.iter_mut().enumerate() {
+        if (ACTIVE >> i) & 1 == 0 {
+            continue;
+        }
+        if STEP == 0 {
+            ch.data = ch.next_data;
+            if ch.data > 0 {
+                set |= 1 << ch.pin;
+            } else {
+                clr |= 1 << ch.pin;
+            }
+        } else if ch.data == STEP {
+            clr |= 1 << ch.pin;
+        }
+    }
+    if set | clr != 0 {
+        let _ = gpio_write_mask(set, clr);
+    }
+    STEP = if STEP + 1 >= STEPS { 0 } else { STEP + 1 };
+}
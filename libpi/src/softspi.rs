@@ -0,0 +1,120 @@
+//! Bit-banged SPI master on arbitrary pins, MSB first, in any of the four
+//! modes. Like the hardware driver it implements `embedded_hal::spi::SpiBus`;
+//! the chip select is left to the caller.
+
+use core::convert::Infallible;
+use crate::pin::*;
+use crate::spi::SpiMode;
+use crate::time::*;
+
+/// SPI master driving `sck`/`mosi` and sampling `miso`.
+pub struct SoftSpi {
+    sck: Pin<Output>,
+    mosi: Pin<Output>,
+    miso: Pin<Input>,
+    cpol: bool,
+    cpha: bool,
+    /// Half a clock period in microseconds.
+    half_us: u32,
+}
+
+impl SoftSpi {
+    /// Takes the bus pins and idles SCK for `mode`. `hz` is an upper bound:
+    /// the clock runs at most that fast, in whole microsecond steps, and as
+    /// fast as the CPU allows if it is above 500 kHz.
+    pub fn new(
+        mut sck: Pin<Output>,
+        mosi: Pin<Output>,
+        miso: Pin<Input>,
+        hz: u32,
+        mode: SpiMode
+    ) -> Self {
+        let cpol = mode as u8 & 2 != 0;
+        sck.write(cpol);
+        Self {
+            sck,
+            mosi,
+            miso,
+            cpol,
+            cpha: mode as u8 & 1 != 0,
+            half_us: 500_000 / hz.max(1),
+        }
+    }
+
+    /// Returns the pins.
+    pub fn free(self) -> (Pin<Output>, Pin<Output>, Pin<Input>) {
+        (self.sck, self.mosi, self.miso)
+    }
+
+    /// Waits half a clock period.
+    fn half(&self) {
+        unsafe { delay_us(self.half_us) };
+    }
+
+    /// Shifts `out` out and returns the byte shifted in.
+    pub fn transfer_byte(&mut self, out: u8) -> u8 {
+        let mut inp = 0;
+        for bit in (0..8).rev() {
+            let b = (out >> bit) & 1 == 1;
+            // CPHA 0 samples on the leading edge, so data must be set up
+            // before it; CPHA 1 sets data up on the leading edge
+            if !self.cpha {
+                self.mosi.write(b);
+            }
+            self.half();
+            self.sck.write(!self.cpol);
+            if self.cpha {
+                self.mosi.write(b);
+            } else {
+                inp = inp << 1 | self.miso.is_high() as u8;
+            }
+            self.half();
+            self.sck.write(self.cpol);
+            if self.cpha {
+                inp = inp << 1 | self.miso.is_high() as u8;
+            }
+        }
+        inp
+    }
+}
+
+impl embedded_hal::spi::ErrorType for SoftSpi {
+    type Error = Infallible;
+}
+
+impl embedded_hal::spi::SpiBus for SoftSpi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        for w in words {
+            *w = self.transfer_byte(0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        for &w in words {
+            self.transfer_byte(w);
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        for i in 0..read.len().max(write.len()) {
+            let b = self.transfer_byte(write.get(i).copied().unwrap_or(0));
+            if let Some(r) = read.get_mut(i) {
+                *r = b;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        for w in words {
+            *w = self.transfer_byte(*w);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
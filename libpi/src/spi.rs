@@ -0,0 +1,420 @@
+//! SPI0 master driver. MOSI/MISO/SCLK are GPIO 10/9/11 and the chip selects
+//! CE0/CE1 are GPIO 8/7. Transfers are full duplex: every byte written clocks
+//! one byte in. `spi_transfer()` polls the FIFOs; `spi_transfer_irq_start()`
+//! runs a transfer from the SPI interrupt instead.
+
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::spi::{Operation, SpiBus};
+use crate::io::*;
+use crate::bits::*;
+use crate::gpio::*;
+use crate::pin::*;
+use crate::mailbox::*;
+use crate::time::*;
+use crate::interrupts::*;
+
+const SPI_BASE: u32 = 0x20204000;
+const SPI_CS: u32 = SPI_BASE;
+const SPI_FIFO: u32 = SPI_BASE + 0x04;
+const SPI_CLK: u32 = SPI_BASE + 0x08;
+
+// SPI_CS bits
+const CS_CPHA: u8 = 2;
+const CS_CPOL: u8 = 3;
+const CS_CLEAR_TX: u8 = 4;
+const CS_CLEAR_RX: u8 = 5;
+const CS_CSPOL: u8 = 6;
+const CS_TA: u8 = 7;
+const CS_INTD: u8 = 9;
+const CS_INTR: u8 = 10;
+const CS_DONE: u8 = 16;
+const CS_RXD: u8 = 17;
+const CS_TXD: u8 = 18;
+const CS_CSPOL0: u8 = 21;
+
+/// Bytes in flight are kept below the RX FIFO size so it cannot overflow.
+const SPI_FIFO_LEN: usize = 16;
+
+const SPI_MOSI: u8 = 10;
+const SPI_MISO: u8 = 9;
+const SPI_SCLK: u8 = 11;
+
+/// Clock polarity (CPOL) and phase (CPHA) as the usual mode number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpiMode {
+    /// Clock idles low, data sampled on the rising edge.
+    Mode0 = 0,
+    /// Clock idles low, data sampled on the falling edge.
+    Mode1 = 1,
+    /// Clock idles high, data sampled on the falling edge.
+    Mode2 = 2,
+    /// Clock idles high, data sampled on the rising edge.
+    Mode3 = 3,
+}
+
+/// Chip select line asserted during transfers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipSelect {
+    /// CE0 on GPIO 8
+    Ce0 = 0,
+    /// CE1 on GPIO 7
+    Ce1 = 1,
+}
+
+impl ChipSelect {
+    fn pin(self) -> u8 {
+        match self {
+            ChipSelect::Ce0 => 8,
+            ChipSelect::Ce1 => 7,
+        }
+    }
+}
+
+/// Bus settings applied by `spi_init()`.
+#[derive(Clone, Copy, Debug)]
+pub struct SpiConfig {
+    /// Requested SCLK rate; the controller rounds down to the core clock
+    /// divided by an even number.
+    pub hz: u32,
+    pub mode: SpiMode,
+    pub cs: ChipSelect,
+    /// `true` if the device selects on a high chip select.
+    pub cs_active_high: bool,
+}
+
+impl SpiConfig {
+    pub const DEFAULT: Self = Self {
+        hz: 1_000_000,
+        mode: SpiMode::Mode0,
+        cs: ChipSelect::Ce0,
+        cs_active_high: false,
+    };
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Buffer of the transfer run by the interrupt handler, if any.
+static mut IRQ_BUF: Option<&'static mut [u8]> = None;
+static mut IRQ_TX: usize = 0;
+static mut IRQ_RX: usize = 0;
+static mut IRQ_DONE: bool = false;
+
+/// Pin of the chip select routed by `spi_set_cs()`.
+static mut CS_PIN: Option<u8> = None;
+
+/// Chip select field value of CE2, which has no pin on this board: the
+/// `SpiBus` operations of `Spi` leave the chip select to the caller.
+const CS_NONE: u32 = 2;
+
+/// Chip select field of the line chosen by `spi_set_cs()`.
+static mut CS_SELECT: u32 = 0;
+
+/// Chip select field asserted by the `SpiBus` operations of `Spi`.
+static mut BUS_CS: u32 = CS_NONE;
+
+/// Routes GPIO 9-11 and the selected chip select to SPI0 and configures the
+/// controller. Returns the actual SCLK rate, or an error if one of the pins
+/// is claimed or in use by another driver.
+pub unsafe fn spi_init(cfg: &SpiConfig) -> Result<u32, GpioError> {
+    let pins = [SPI_MISO, SPI_MOSI, SPI_SCLK];
+    gpio_reserve_all(&pins)?;
+    put32(SPI_CS, 1 << CS_CLEAR_TX | 1 << CS_CLEAR_RX);
+    if let Err(e) = spi_set_cs(cfg.cs, cfg.cs_active_high) {
+        pins.into_iter().for_each(|pin| gpio_unreserve(pin));
+        return Err(e);
+    }
+    for pin in pins {
+        gpio_set_fn(pin, 0b100).unwrap(); // ALT0
+    }
+    spi_set_mode(cfg.mode);
+    Ok(spi_set_hz(cfg.hz))
+}
+
+/// Stops the controller and returns the SPI pins to inputs.
+#[allow(static_mut_refs)]
+pub unsafe fn spi_disable() {
+    irq_disable(SPI_IRQ);
+    put32(SPI_CS, 1 << CS_CLEAR_TX | 1 << CS_CLEAR_RX);
+    dsb();
+    IRQ_BUF = None;
+    for pin in [SPI_MISO, SPI_MOSI, SPI_SCLK].into_iter().chain(CS_PIN.take()) {
+        gpio_set_input(pin).unwrap();
+        gpio_unreserve(pin);
+    }
+}
+
+/// Sets SCLK as close to `hz` as the divider allows without exceeding it and
+/// returns the actual rate.
+pub unsafe fn spi_set_hz(hz: u32) -> u32 {
+    let core = mbox_core_clock();
+    // the divider must be even; 0 means 65536
+    let mut cdiv = core.div_ceil(hz.max(1));
+    cdiv = (cdiv + 1) & !1;
+    let cdiv = cdiv.clamp(2, 65536);
+    put32(SPI_CLK, cdiv & 0xFFFF);
+    dsb();
+    core / cdiv
+}
+
+/// Sets the clock polarity and phase.
+pub unsafe fn spi_set_mode(mode: SpiMode) {
+    let mode = mode as u32;
+    let mut cs = get32(SPI_CS);
+    cs = if mode & 1 == 1 { bit_set(cs, CS_CPHA) } else { bit_clr(cs, CS_CPHA) };
+    cs = if mode & 2 == 2 { bit_set(cs, CS_CPOL) } else { bit_clr(cs, CS_CPOL) };
+    put32(SPI_CS, cs);
+    dsb();
+}
+
+/// Selects the chip select line driven during transfers and its polarity,
+/// routing its pin to SPI0 and releasing the previous one. Fails, leaving the
+/// previous line selected, if the pin is claimed or in use by another driver.
+#[allow(static_mut_refs)]
+pub unsafe fn spi_set_cs(cs: ChipSelect, active_high: bool) -> Result<(), GpioError> {
+    if CS_PIN != Some(cs.pin()) {
+        gpio_reserve(cs.pin())?;
+        if let Some(old) = CS_PIN.take() {
+            gpio_set_input(old).unwrap();
+            gpio_unreserve(old);
+        }
+        gpio_set_fn(cs.pin(), 0b100).unwrap(); // ALT0
+        CS_PIN = Some(cs.pin());
+    }
+    let bit = CS_CSPOL0 + cs as u8;
+    let mut reg = bits_modify(get32(SPI_CS), 1, 0, cs as u32);
+    reg = if active_high {
+        bit_set(bit_set(reg, bit), CS_CSPOL)
+    } else {
+        bit_clr(bit_clr(reg, bit), CS_CSPOL)
+    };
+    put32(SPI_CS, reg);
+    dsb();
+    CS_SELECT = cs as u32;
+    Ok(())
+}
+
+/// Clears the FIFOs and asserts chip select field `cs` (sets TA) for
+/// `spi_shift()`, first releasing another line left asserted. Does nothing if
+/// `cs` is already asserted.
+#[allow(static_mut_refs)]
+unsafe fn spi_begin(cs: u32) {
+    assert!(IRQ_BUF.is_none(), "SPI interrupt transfer in progress");
+    let reg = get32(SPI_CS);
+    if bit_is_set(reg, CS_TA) && reg & 0b11 == cs {
+        return;
+    }
+    let reg = bits_modify(bit_clr(reg, CS_TA), 1, 0, cs);
+    put32(SPI_CS, reg);
+    put32(SPI_CS, reg | 1 << CS_CLEAR_TX | 1 << CS_CLEAR_RX | 1 << CS_TA);
+}
+
+/// Shifts `len` bytes, sending `tx(i)` as byte `i` and passing each received
+/// byte to `rx(i, byte)`. Leaves the chip select asserted.
+/// Precondition: `spi_begin()` has been called.
+unsafe fn spi_shift(len: usize, mut tx: impl FnMut(usize) -> u8, mut rx: impl FnMut(usize, u8)) {
+    let (mut sent, mut received) = (0, 0);
+    while received < len {
+        while sent < len && sent - received < SPI_FIFO_LEN
+            && bit_is_set(get32(SPI_CS), CS_TXD) {
+            put32(SPI_FIFO, tx(sent) as u32);
+            sent += 1;
+        }
+        while received < len && bit_is_set(get32(SPI_CS), CS_RXD) {
+            rx(received, get32(SPI_FIFO) as u8);
+            received += 1;
+        }
+    }
+    while !bit_is_set(get32(SPI_CS), CS_DONE) {}
+}
+
+/// Releases the chip select (clears TA).
+unsafe fn spi_end() {
+    put32(SPI_CS, bit_clr(get32(SPI_CS), CS_TA));
+    dsb();
+}
+
+/// Runs a polled transfer of `len` bytes with the chip select asserted for
+/// its duration; see `spi_shift()`.
+unsafe fn spi_run(len: usize, tx: impl FnMut(usize) -> u8, rx: impl FnMut(usize, u8)) {
+    spi_begin(CS_SELECT);
+    spi_shift(len, tx, rx);
+    spi_end();
+}
+
+/// Sends `buf` and replaces its contents with the bytes received.
+pub unsafe fn spi_transfer(buf: &mut [u8]) {
+    let p = buf.as_mut_ptr();
+    spi_run(buf.len(), |i| *p.add(i), |i, b| *p.add(i) = b);
+}
+
+/// Sends `buf`, discarding the bytes received.
+pub unsafe fn spi_write(buf: &[u8]) {
+    spi_run(buf.len(), |i| buf[i], |_, _| {});
+}
+
+/// Fills `buf` with bytes received while sending zeros.
+pub unsafe fn spi_read(buf: &mut [u8]) {
+    spi_run(buf.len(), |_| 0, |i, b| buf[i] = b);
+}
+
+/// Starts an interrupt-driven transfer of `buf`; its contents are replaced by
+/// the bytes received. Collect the buffer with `spi_transfer_irq_poll()`.
+/// Precondition: no other interrupt transfer is running and interrupts are
+/// set up with `interrupt_init()`.
+#[allow(static_mut_refs)]
+pub unsafe fn spi_transfer_irq_start(buf: &'static mut [u8]) {
+    assert!(IRQ_BUF.is_none(), "SPI interrupt transfer in progress");
+    IRQ_TX = 0;
+    IRQ_RX = 0;
+    IRQ_DONE = buf.is_empty();
+    IRQ_BUF = Some(buf);
+    if IRQ_DONE {
+        return;
+    }
+    let cs = bits_modify(bit_clr(get32(SPI_CS), CS_TA), 1, 0, CS_SELECT);
+    put32(SPI_CS, cs);
+    let cs = cs | 1 << CS_CLEAR_TX | 1 << CS_CLEAR_RX;
+    put32(SPI_CS, cs | 1 << CS_INTD | 1 << CS_INTR | 1 << CS_TA);
+    dsb();
+    irq_enable(SPI_IRQ);
+}
+
+/// Returns the buffer of the interrupt transfer once it has completed.
+#[allow(static_mut_refs)]
+pub unsafe fn spi_transfer_irq_poll() -> Option<&'static mut [u8]> {
+    if IRQ_DONE {
+        IRQ_DONE = false;
+        IRQ_BUF.take()
+    } else {
+        None
+    }
+}
+
+/// Moves bytes between the FIFOs and the interrupt transfer buffer and ends
+/// the transfer once every byte is received. Called from `interrupt_vector`.
+#[allow(static_mut_refs)]
+pub unsafe fn spi_irq_handler() {
+    let Some(buf) = IRQ_BUF.as_mut() else {
+        irq_disable(SPI_IRQ);
+        return;
+    };
+    while IRQ_RX < buf.len() && bit_is_set(get32(SPI_CS), CS_RXD) {
+        buf[IRQ_RX] = get32(SPI_FIFO) as u8;
+        IRQ_RX += 1;
+    }
+    while IRQ_TX < buf.len() && IRQ_TX - IRQ_RX < SPI_FIFO_LEN
+        && bit_is_set(get32(SPI_CS), CS_TXD) {
+        put32(SPI_FIFO, buf[IRQ_TX] as u32);
+        IRQ_TX += 1;
+    }
+    if IRQ_RX == buf.len() {
+        let cs = get32(SPI_CS);
+        put32(SPI_CS, bit_clr(bit_clr(bit_clr(cs, CS_TA), CS_INTD), CS_INTR));
+        dsb();
+        irq_disable(SPI_IRQ);
+        IRQ_DONE = true;
+    }
+}
+
+/// Set once `Spi::take()` has handed out the bus.
+static SPI_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Owner of the SPI0 bus. As an `embedded_hal::spi::SpiBus` it drives no chip
+/// select, so it can be shared through e.g. `ExclusiveDevice` with a GPIO
+/// chip select; as an `embedded_hal::spi::SpiDevice` it asserts the line
+/// chosen in its `SpiConfig` across all operations of a transaction and
+/// releases it at the end.
+pub struct Spi {
+    _private: (),
+}
+
+impl Spi {
+    /// Initializes SPI0 with `cfg` and returns the device the first time it
+    /// is called, `None` after. Fails if one of the SPI pins is claimed.
+    pub fn take(cfg: &SpiConfig) -> Result<Option<Self>, GpioError> {
+        if SPI_TAKEN.swap(true, Ordering::AcqRel) {
+            return Ok(None);
+        }
+        if let Err(e) = unsafe { spi_init(cfg) } {
+            SPI_TAKEN.store(false, Ordering::Release);
+            return Err(e);
+        }
+        Ok(Some(Self { _private: () }))
+    }
+}
+
+impl embedded_hal::spi::ErrorType for Spi {
+    type Error = Infallible;
+}
+
+impl SpiBus for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        unsafe {
+            spi_begin(BUS_CS);
+            spi_shift(words.len(), |_| 0, |i, b| words[i] = b);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        unsafe {
+            spi_begin(BUS_CS);
+            spi_shift(words.len(), |i| words[i], |_, _| {});
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        let len = read.len().max(write.len());
+        let tx = |i| write.get(i).copied().unwrap_or(0);
+        unsafe {
+            spi_begin(BUS_CS);
+            spi_shift(len, tx, |i, b| if let Some(r) = read.get_mut(i) { *r = b });
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Infallible> {
+        let p = words.as_mut_ptr();
+        unsafe {
+            spi_begin(BUS_CS);
+            spi_shift(words.len(), |i| *p.add(i), |i, b| *p.add(i) = b);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        // every operation waits for DONE before returning
+        Ok(())
+    }
+}
+
+impl embedded_hal::spi::SpiDevice for Spi {
+    /// Runs the operations through the `SpiBus` implementation with the
+    /// configured chip select asserted in place of none.
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        unsafe {
+            BUS_CS = CS_SELECT;
+            spi_begin(BUS_CS);
+        }
+        for op in operations {
+            match op {
+                Operation::Read(buf) => SpiBus::read(self, buf)?,
+                Operation::Write(buf) => SpiBus::write(self, buf)?,
+                Operation::Transfer(read, write) => SpiBus::transfer(self, read, write)?,
+                Operation::TransferInPlace(buf) => SpiBus::transfer_in_place(self, buf)?,
+                Operation::DelayNs(ns) => unsafe { delay_us(ns.div_ceil(1000)) },
+            }
+        }
+        unsafe {
+            spi_end();
+            BUS_CS = CS_NONE;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,13 @@
 /// Simple timer related structs and operations. 
 
 use core::arch::asm;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+pub use core::time::Duration;
 use crate::io::*;
 use crate::threads::*;
 
 const SYSTEM_TIMER: u32 = 0x20003004;
+const SYSTEM_TIMER_HI: u32 = 0x20003008;
 
 /// nop
 #[inline(always)]
@@ -12,30 +15,176 @@ pub unsafe fn wait() {
     asm!("nop", options(nostack, preserves_flags));
 }
 
-/// Delays execution for `ms` milliseconds using the system timer.
-pub unsafe fn delay_ms(ms: u32) {
-    let start = get32(SYSTEM_TIMER);
-    while get32(SYSTEM_TIMER).wrapping_sub(start) < (ms * 1000) {
+/// Busy-waits for at least `n` CPU cycles.
+#[inline(always)]
+pub unsafe fn delay_cycles(n: u32) {
+    for _ in 0..n {
+        wait();
+    }
+}
+
+/// Returns the low 32 bits of the free-running 1 MHz system timer.
+pub unsafe fn timer_get_usec() -> u32 {
+    get32(SYSTEM_TIMER)
+}
+
+/// Returns the full 64-bit system timer, which does not wrap in practice.
+pub unsafe fn timer_get_usec64() -> u64 {
+    // re-read if the low word wrapped between the two reads
+    let mut hi = get32(SYSTEM_TIMER_HI);
+    let mut lo = get32(SYSTEM_TIMER);
+    let hi2 = get32(SYSTEM_TIMER_HI);
+    if hi != hi2 {
+        hi = hi2;
+        lo = get32(SYSTEM_TIMER);
+    }
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Returns `d` in whole microseconds, saturating.
+fn duration_us(d: Duration) -> u64 {
+    d.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(d.subsec_micros() as u64)
+}
+
+/// A point in time, in microseconds of the system timer since boot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub unsafe fn now() -> Self {
+        Self(timer_get_usec64())
+    }
+
+    /// Returns the instant `us` microseconds after boot.
+    pub const fn from_micros(us: u64) -> Self {
+        Self(us)
+    }
+
+    /// Returns the microseconds since boot.
+    pub const fn as_micros(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed since `self`.
+    pub unsafe fn elapsed(self) -> Duration {
+        Self::now().saturating_duration_since(self)
+    }
+
+    /// Returns the time from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn saturating_duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn checked_add(self, d: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_us(d)).map(Self)
+    }
+
+    pub fn checked_sub(self, d: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration_us(d)).map(Self)
+    }
+
+    /// Returns `self + d`, or the last representable instant (never reached)
+    /// if that overflows.
+    pub fn saturating_add(self, d: Duration) -> Instant {
+        Self(self.0.saturating_add(duration_us(d)))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, d: Duration) -> Instant {
+        self.checked_add(d).expect("overflow adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, d: Duration) {
+        *self = *self + d;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, d: Duration) -> Instant {
+        self.checked_sub(d).expect("overflow subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, d: Duration) {
+        *self = *self - d;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Saturates to zero if `earlier` is later than `self`.
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+/// Busy-waits until `deadline`.
+pub unsafe fn delay_until(deadline: Instant) {
+    while Instant::now() < deadline {
         wait();
     }
 }
 
+/// Busy-waits for at least `d`.
+pub unsafe fn delay(d: Duration) {
+    delay_until(Instant::now().saturating_add(d));
+}
+
+/// Busy-waits for at least `us` microseconds using the system timer.
+pub unsafe fn delay_us(us: u32) {
+    delay(Duration::from_micros(us as u64));
+}
+
+/// Delays execution for `ms` milliseconds using the system timer.
+pub unsafe fn delay_ms(ms: u32) {
+    delay(Duration::from_millis(ms as u64));
+}
+
 /// A simple system timer object. 
 pub struct Timer {
-    stop_time: u32  
+    deadline: Instant,
 } 
 
 impl Timer {
     /// Returns Timer struct that expires after `ms` milliseconds.
     pub unsafe fn set(ms: u32) -> Self {
-        Self {
-            stop_time: get32(SYSTEM_TIMER).wrapping_add(ms * 1000)
-        }
+        Self::after(Duration::from_millis(ms as u64))
     }
 
-    /// Returns `true` if timer has expired (`ms` milliseconds after `start()` called).
+    /// Returns Timer struct that expires after `d`; one that would expire
+    /// beyond the range of `Instant` never does.
+    pub unsafe fn after(d: Duration) -> Self {
+        Self::at(Instant::now().saturating_add(d))
+    }
+
+    /// Returns Timer struct that expires at `deadline`.
+    pub fn at(deadline: Instant) -> Self {
+        Self { deadline }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns `true` if timer has expired.
     pub unsafe fn done(&self) -> bool {
-        self.stop_time.wrapping_sub(get32(SYSTEM_TIMER)) as i32 <= 0
+        Instant::now() >= self.deadline
+    }
+
+    /// Returns the time left until the timer expires, or zero.
+    pub unsafe fn remaining(&self) -> Duration {
+        self.deadline - Instant::now()
     }
 
     /// Returns only when the timer has expired otherwise yields.
@@ -45,4 +194,3 @@ impl Timer {
         }
     }
 }
-
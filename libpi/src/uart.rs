@@ -4,6 +4,9 @@ use crate::bits::*;
 use crate::io::*;
 use crate::gpio::*;
 use crate::time::*;
+use crate::mailbox::*;
+use crate::pin::*;
+use crate::serial::*;
 
 const AUX_BASE: u32 = 0x20215000;
 const AUX_ENABLES: u32 = AUX_BASE + 0x04;
@@ -15,21 +18,144 @@ const AUX_MU_CNTL: u32 = AUX_BASE + 0x60;
 const AUX_MU_STAT: u32 = AUX_BASE + 0x64;
 const AUX_MU_BAUD: u32 = AUX_BASE + 0x68;
 
-/// called first to setup UART to 8n1 115200 baud, no interrupts
-pub unsafe fn uart_init() {
+/// Line settings of the mini-UART, built with chained setters, e.g.
+/// `UartConfig::new().baud(921600)`. It always uses 1 stop bit and no parity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UartConfig {
+    baud: u32,
+    data_bits: DataBits,
+    /// `None` asks the firmware for the core clock.
+    core_clock: Option<u32>,
+}
+
+/// Baud rate achieved for a requested one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaudSettings {
+    /// `AUX_MU_BAUD` value.
+    pub divisor: u32,
+    pub requested: u32,
+    pub actual: u32,
+}
+
+impl BaudSettings {
+    /// Returns the deviation of the actual from the requested baud rate in
+    /// percent. Receivers usually tolerate about 2-3%.
+    pub fn error_percent(&self) -> f32 {
+        (self.actual as f32 - self.requested as f32) * 100.0 / self.requested as f32
+    }
+}
+
+impl UartConfig {
+    /// 115200 8N1 at the core clock reported by the firmware.
+    pub const DEFAULT: Self = Self {
+        baud: 115200,
+        data_bits: DataBits::Eight,
+        core_clock: None,
+    };
+
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    pub const fn baud(mut self, baud: u32) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Sets 7 or 8 data bits.
+    /// Precondition: `bits` is `DataBits::Seven` or `DataBits::Eight`
+    pub const fn data_bits(mut self, bits: DataBits) -> Self {
+        assert!(matches!(bits, DataBits::Seven | DataBits::Eight));
+        self.data_bits = bits;
+        self
+    }
+
+    /// Computes the divisor from `hz` instead of the core clock reported by
+    /// the firmware, e.g. if `config.txt` changes it after boot.
+    pub const fn core_clock(mut self, hz: u32) -> Self {
+        self.core_clock = Some(hz);
+        self
+    }
+
+    /// Returns the divisor and actual baud rate for a core clock of
+    /// `core_clock` Hz.
+    pub fn baud_settings(&self, core_clock: u32) -> BaudSettings {
+        let baud = self.baud.max(1);
+        // in u64 so that no baud rate overflows
+        let (clock, b) = (core_clock as u64, baud as u64);
+        let divisor = ((clock + 4 * b) / (8 * b)).clamp(1, 0x10000) as u32 - 1;
+        BaudSettings {
+            divisor,
+            requested: baud,
+            actual: core_clock / (8 * (divisor + 1)),
+        }
+    }
+}
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static mut UART_CONFIG: UartConfig = UartConfig::DEFAULT;
+
+/// called first to setup UART to 8n1 115200 baud, no interrupts. Fails if
+/// GPIO 14 or 15 is claimed or in use by another driver.
+pub unsafe fn uart_init() -> Result<(), GpioError> {
+    uart_init_config(&UartConfig::DEFAULT).map(|_| ())
+}
+
+/// setup UART to 8n1 at `baud`, no interrupts. Fails if GPIO 14 or 15 is
+/// claimed or in use by another driver.
+pub unsafe fn uart_init_baud(baud: u32) -> Result<(), GpioError> {
+    uart_init_config(&UartConfig::new().baud(baud)).map(|_| ())
+}
+
+/// setup UART with `cfg`, no interrupts. Returns the achieved baud rate, or an
+/// error if GPIO 14 or 15 is claimed or in use by another driver (e.g. UART0;
+/// see `pl011_disable()`).
+pub unsafe fn uart_init_config(cfg: &UartConfig) -> Result<BaudSettings, GpioError> {
+    gpio_reserve_all(&[14, 15])?;
     dsb();
-    gpio_set_fn(14, 0b010); // set to TXD1
-    gpio_set_fn(15, 0b010); // set to RXD1
+    gpio_set_fn(14, 0b010).unwrap(); // set to TXD1
+    gpio_set_fn(15, 0b010).unwrap(); // set to RXD1
     dsb();
     put32(AUX_ENABLES, bit_set(get32(AUX_ENABLES), 0)); // enable mini-UART
     dsb();
-    put32(AUX_MU_CNTL, 0); // disable TX/RX, control flow
-    put32(AUX_MU_LCR, 0b11); // disable DLAB access, set to 8-bit mode
     put32(AUX_MU_IER, 0); // disable interrupts
+    Ok(uart_apply(cfg))
+}
+
+/// Switches the running UART to `cfg` (e.g. a higher baud rate for a log
+/// dump) once everything queued has been sent. Returns the achieved baud
+/// rate. The other end must switch too, or received data is garbled.
+pub unsafe fn uart_reconfigure(cfg: &UartConfig) -> BaudSettings {
+    uart_flush_tx();
+    uart_apply(cfg)
+}
+
+/// Returns the settings the UART was last configured with.
+pub unsafe fn uart_config() -> UartConfig {
+    UART_CONFIG
+}
+
+/// Programs the line settings with TX/RX disabled and the FIFOs cleared.
+unsafe fn uart_apply(cfg: &UartConfig) -> BaudSettings {
+    let clock = match cfg.core_clock {
+        Some(hz) => hz,
+        None => mbox_core_clock(),
+    };
+    let settings = cfg.baud_settings(clock);
+    put32(AUX_MU_CNTL, 0); // disable TX/RX, control flow
+    // disable DLAB access, set to 7- or 8-bit mode
+    put32(AUX_MU_LCR, if cfg.data_bits == DataBits::Eight { 0b11 } else { 0 });
     put32(AUX_MU_IIR, 0b110); // clear FIFO queues
-    put32(AUX_MU_BAUD, 270); // 115200 baud (assuming 250 MHz clock)
+    put32(AUX_MU_BAUD, settings.divisor);
     put32(AUX_MU_CNTL, 0b11); // re-enable TX/RX
     dsb();
+    UART_CONFIG = *cfg;
+    settings
 }
 
 /// disable the UART
@@ -38,6 +164,8 @@ pub unsafe fn uart_disable() {
     put32(AUX_MU_CNTL, 0); // disable TX/RX
     put32(AUX_MU_IIR, 0b110); // clear FIFO queues
     put32(AUX_ENABLES, bit_clr(get32(AUX_ENABLES), 0)); // disable mini-UART
+    gpio_unreserve(14);
+    gpio_unreserve(15);
 }
 
 /// Returns one byte from the RX FIFO. If FIFO is empty, blocks until there is 
@@ -95,3 +223,22 @@ pub unsafe fn uart_flush_tx() {
     }
 }
 
+
+/// Handle implementing `SerialPort` for the mini-UART, which has no parity or
+/// framing error flags.
+/// Precondition for use: `uart_init()` has been called.
+pub struct MiniUart;
+
+impl SerialPort for MiniUart {
+    fn write_byte(&mut self, byte: u8) {
+        unsafe { uart_put8(byte) };
+    }
+
+    fn try_read_byte(&mut self) -> Option<Result<u8, SerialError>> {
+        unsafe { uart_get8_async().map(Ok) }
+    }
+
+    fn flush(&mut self) {
+        unsafe { uart_flush_tx() };
+    }
+}
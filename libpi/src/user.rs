@@ -1,10 +1,15 @@
 #![allow(non_snake_case)]
 /// Implementation for user threads for demo. 
 
+use crate::io::*;
 use crate::gpio::*;
+use crate::pin::*;
 use crate::time::*;
 use crate::uart::*;
 use crate::threads::*;
+use crate::framebuffer::*;
+use crate::boot::*;
+use crate::watchdog::*;
 
 const SYSTEM_CHECK_LOG: &str = r#"Commencing System Check
 Memory Unit: Green
@@ -25,25 +30,57 @@ Equipment Status: Green
 All Systems Green
 Combat Preparations Complete_"#;
 
-/// Toggles GPIO 25 (white) every second. Yields when done.
+/// GPIO pin assignments for the demo.
+pub struct DemoPins {
+    pub blink: u8,   // toggled by threadA and 'r'
+    pub log: u8,     // on while threadB prints, toggled by 'g'
+    pub button: u8,  // rising edge enters the interrupt console
+    pub irq_led: u8, // on while the interrupt console is active
+}
+
+/// Pins used by the demo threads and the interrupt console.
+pub static mut PINS: DemoPins = DemoPins {
+    blink: 25,
+    log: 20,
+    button: 21,
+    irq_led: 5,
+};
+
+/// Overrides `PINS` with `rustypi.pin.{blink,log,button,irq_led}=<n>` from
+/// the kernel command line. Pins that do not exist or that a driver has
+/// reserved (e.g. 14/15 for the UART) are ignored.
+pub unsafe fn pins_configure(boot: &BootInfo) {
+    let pin = |key, default| match boot.param_u32(key) {
+        Some(n) if n < GPIO_PINS as u32 && !gpio_is_reserved(n as u8) => n as u8,
+        _ => default,
+    };
+    PINS.blink = pin("rustypi.pin.blink", PINS.blink);
+    PINS.log = pin("rustypi.pin.log", PINS.log);
+    PINS.button = pin("rustypi.pin.button", PINS.button);
+    PINS.irq_led = pin("rustypi.pin.irq_led", PINS.irq_led);
+}
+
+/// Toggles `PINS.blink` (GPIO 25, white) every second. Yields when done.
 pub unsafe extern "C" fn threadA(i: u32) {
     let _ = i;
     loop {
-        gpio_toggle(25);
+        gpio_toggle(PINS.blink).unwrap();
         Timer::set(1000).wait_and_yield();
     }
 }
 
-/// Prints the `SYSTEM_CHECK_LOG` at a rate of 10B/s, but also yielding.
+/// Prints the `SYSTEM_CHECK_LOG` at a rate of 10B/s on the UART and the
+/// console, but also yielding.
 pub unsafe extern "C" fn threadB(i: u32) {
     let _ = i;
     Timer::set(1000).wait_and_yield();
-    gpio_set_on(20);
+    gpio_set_on(PINS.log).unwrap();
     for c in SYSTEM_CHECK_LOG.chars() {
         uart_put8(c as u8);
+        console_put8(c as u8);
         Timer::set(100).wait_and_yield();
     }
-    gpio_set_off(20);
+    gpio_set_off(PINS.log).unwrap();
     exit_thread();
 }
 
@@ -51,11 +88,34 @@ pub unsafe extern "C" fn threadB(i: u32) {
 pub unsafe extern "C" fn threadC(i: u32) {
     for _ in 0..i {
         uart_print("\n(^_^)\n");
+        console_print("\n(^_^)\n");
         Timer::set(1000).wait_and_yield(); 
     }
     exit_thread();
 }
 
+/// GPIO event callback for `PINS.button`: while the pin stays high, echoes
+/// keystrokes on the mini-UART and controls GPIO output with them. Threads do
+/// not run meanwhile, so it feeds the watchdog itself.
+pub unsafe fn button_console(pin: u8) {
+    gpio_set_on(PINS.irq_led).unwrap();
+    while gpio_read(pin) == Ok(1) {
+        watchdog_feed();
+        while uart_has_data() {
+            let c = uart_get8() as char;
+            uart_put8(c as u8);
+            match c {
+                'g' => gpio_toggle(PINS.log).unwrap(),
+                'r' => gpio_toggle(PINS.blink).unwrap(),
+                i if i.is_numeric() => spawn(i),
+                _ => continue
+            };
+        }
+        dsb();
+    }
+    gpio_set_off(PINS.irq_led).unwrap();
+}
+
 /// Spawns a threadC given char `c` that represents an ASCII digit.
 #[allow(static_mut_refs)]
 pub unsafe fn spawn(c: char) {
@@ -0,0 +1,100 @@
+//! Reset, halt and watchdog support through the power manager. Once started,
+//! the watchdog resets the board unless it is fed before its timeout runs
+//! out; `watchdog_idle_thread` ties feeding to the scheduler so a thread that
+//! never yields causes a reset.
+
+use crate::io::*;
+use crate::threads::*;
+
+const PM_BASE: u32 = 0x20100000;
+const PM_RSTC: u32 = PM_BASE + 0x1C;
+const PM_RSTS: u32 = PM_BASE + 0x20;
+const PM_WDOG: u32 = PM_BASE + 0x24;
+
+/// Every power manager write must carry this password in bits 31-24.
+const PM_PASSWORD: u32 = 0x5A000000;
+
+const PM_RSTC_WRCFG_CLR: u32 = 0xFFFFFFCF;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x00000020;
+const PM_RSTC_RESET: u32 = 0x00000102;
+
+/// Boot partition bits in PM_RSTS; partition 63 tells the firmware to halt.
+const PM_RSTS_PARTITION_CLR: u32 = 0xFFFFFAAA;
+const PM_RSTS_HALT: u32 = 0x555;
+
+/// The watchdog counts down in 16 us ticks (65536 per second).
+const PM_WDOG_TIME_MASK: u32 = 0x000FFFFF;
+
+/// Longest timeout the 20-bit counter allows, about 16 s.
+pub const WATCHDOG_MAX_MS: u32 = PM_WDOG_TIME_MASK * 1000 / 65536;
+
+/// Ticks loaded by `watchdog_feed()`.
+static mut WDOG_TICKS: u32 = 0;
+
+/// Converts `ms` to watchdog ticks, clamped to the counter.
+fn watchdog_ticks(ms: u32) -> u32 {
+    ((ms as u64 * 65536 / 1000) as u32).clamp(1, PM_WDOG_TIME_MASK)
+}
+
+/// Loads `ticks` into the counter and arms a full reset when it expires.
+unsafe fn watchdog_arm(ticks: u32) {
+    dsb();
+    let rstc = get32(PM_RSTC) & PM_RSTC_WRCFG_CLR;
+    put32(PM_WDOG, PM_PASSWORD | ticks);
+    put32(PM_RSTC, PM_PASSWORD | rstc | PM_RSTC_WRCFG_FULL_RESET);
+    dsb();
+}
+
+/// Starts the watchdog with a timeout of `timeout_ms` (at most
+/// `WATCHDOG_MAX_MS`). The board resets unless `watchdog_feed()` is called
+/// within every timeout.
+pub unsafe fn watchdog_start(timeout_ms: u32) {
+    WDOG_TICKS = watchdog_ticks(timeout_ms);
+    watchdog_arm(WDOG_TICKS);
+}
+
+/// Restarts the countdown of a running watchdog.
+pub unsafe fn watchdog_feed() {
+    if WDOG_TICKS != 0 {
+        watchdog_arm(WDOG_TICKS);
+    }
+}
+
+/// Stops the watchdog.
+pub unsafe fn watchdog_stop() {
+    WDOG_TICKS = 0;
+    dsb();
+    put32(PM_RSTC, PM_PASSWORD | PM_RSTC_RESET);
+    dsb();
+}
+
+/// Returns the milliseconds left before the watchdog fires.
+pub unsafe fn watchdog_remaining_ms() -> u32 {
+    (get32(PM_WDOG) & PM_WDOG_TIME_MASK) * 1000 / 65536
+}
+
+/// Resets the board.
+pub unsafe fn reboot() -> ! {
+    watchdog_arm(10);
+    loop {}
+}
+
+/// Resets the board into a halt: the firmware stops instead of booting again.
+pub unsafe fn halt() -> ! {
+    let rsts = get32(PM_RSTS) & PM_RSTS_PARTITION_CLR;
+    put32(PM_RSTS, PM_PASSWORD | rsts | PM_RSTS_HALT);
+    reboot()
+}
+
+/// Thread body that starts the watchdog with `timeout_ms` and feeds it each
+/// time the scheduler gets around to it, e.g.
+/// `SCHEDULER.fork(watchdog_idle_thread, 2000)`. Threads that stop yielding
+/// starve it and the board resets, as do long-running interrupt handlers
+/// unless they call `watchdog_feed()` themselves.
+pub unsafe extern "C" fn watchdog_idle_thread(timeout_ms: u32) {
+    watchdog_start(timeout_ms);
+    loop {
+        watchdog_feed();
+        yield_thread();
+    }
+}
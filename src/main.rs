@@ -10,19 +10,39 @@ use libpi::uart::*;
 use libpi::threads::*;
 use libpi::interrupts::*;
 use libpi::user::*;
+use libpi::memmap::*;
+use libpi::mailbox::*;
+use libpi::framebuffer::*;
+use libpi::boot::*;
+use libpi::watchdog::*;
 
-/// The entry point for the bare-metal kernel
+/// The entry point for the bare-metal kernel. Baud rate and pin assignments
+/// can be overridden from `cmdline.txt` (`rustypi.baud=`, `rustypi.pin.*=`),
+/// and `rustypi.watchdog_ms=` resets the board if the threads stop yielding.
 #[no_mangle]
 #[allow(static_mut_refs)]
-pub unsafe extern "C" fn notmain() -> ! {
-    uart_init();
-    gpio_set_output(5);
-    gpio_set_output(20);
-    gpio_set_output(25);
+pub unsafe extern "C" fn notmain(boot: &BootInfo) -> ! {
+    // an unusable baud rate would leave no output to report it on
+    let baud = boot.param_u32("rustypi.baud")
+        .filter(|&baud| baud > 0 && baud <= mbox_core_clock() / 8)
+        .unwrap_or(115200);
+    uart_init_baud(baud).unwrap();
+    if let Some(mem) = boot.mem.or_else(|| mbox_arm_memory().ok()) {
+        memmap_check_ram(mem.end);
+    }
+    let _ = console_init(640, 480); // UART-only if there is no display
+    pins_configure(boot);
+    gpio_set_output(PINS.irq_led).unwrap();
+    gpio_set_output(PINS.log).unwrap();
+    gpio_set_output(PINS.blink).unwrap();
+    gpio_set_input_pulldown(PINS.button).unwrap();
     interrupt_init();
-    gpio_rising_edge_init(21);
+    gpio_on_event(PINS.button, EdgeKind::Rising, button_console).unwrap();
     SCHEDULER.fork(threadA, 5); // args don't matter 
     SCHEDULER.fork(threadB, 42); // args don't matter 
+    if let Some(ms) = boot.param_u32("rustypi.watchdog_ms") {
+        SCHEDULER.fork(watchdog_idle_thread, ms);
+    }
     SCHEDULER.cswitch();
     loop {
         wait();
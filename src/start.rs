@@ -1,46 +1,60 @@
 use core::arch::asm;
+use libpi::memmap::*;
+use libpi::boot::*;
 
+#[allow(improper_ctypes)] // only ever called from Rust
 extern "C" {
-    fn notmain(); 
+    fn notmain(boot: &BootInfo);
 }
 
-/// Enter super mode, disable interrupts, and initialize stack pointer 
+/// Enter super mode, disable interrupts, and initialize the stack pointer of
+/// every processor mode from the stacks reserved in `memory.ld`. r0-r2 from
+/// the firmware are left untouched for `_start2`.
 #[no_mangle]
 #[link_section = ".text.boot"]
 pub unsafe extern "C" fn _start() -> ! {
     asm!(
-        "mov r0, #0x13", // 0b10011 - switch to svc      
-        "orr r0, r0, #(1 << 7)", // disable IRQ
-        "msr cpsr, r0",
-        "mcr p15, 0, r1, c7, c5, 4", // prefetch flush
-        "ldr sp, =0x80000",  
-        "bl _start2",     
+        "mov r3, #0x13", // 0b10011 - switch to svc
+        "orr r3, r3, #(1 << 7)", // disable IRQ
+        "msr cpsr, r3",
+        "mov r3, #0",
+        "mcr p15, 0, r3, c7, c5, 4", // prefetch flush
+        "cps #0x11", // fiq
+        "ldr sp, =__symbol_fiq_stack_end__",
+        "cps #0x12", // irq
+        "ldr sp, =__symbol_irq_stack_end__",
+        "cps #0x17", // abort
+        "ldr sp, =__symbol_abt_stack_end__",
+        "cps #0x1B", // undefined
+        "ldr sp, =__symbol_und_stack_end__",
+        "cps #0x1F", // system (banked with user)
+        "ldr sp, =__symbol_sys_stack_end__",
+        "cps #0x13", // back to svc
+        "mcr p15, 0, r3, c7, c5, 4", // prefetch flush
+        "ldr sp, =__symbol_svc_stack_end__",
+        "bl _start2",
         options(noreturn)
     );
 }
 
-/// Zeroes out bss and calls notmain()
+/// Zeroes out bss, checks the memory layout, parses the boot parameters in
+/// `r0`-`r2` and calls notmain()
 #[no_mangle]
-pub unsafe extern "C" fn _start2() -> ! {
-    let bss_start: *mut u32;
-    let bss_end: *mut u32;
-    asm!(
-        "ldr {0}, =__symbol_bss_start__",
-        "ldr {1}, =__symbol_bss_end__",
-        out(reg) bss_start,
-        out(reg) bss_end
-    );
-
-    let mut ptr = bss_start;
-    while ptr < bss_end {
+pub unsafe extern "C" fn _start2(r0: u32, r1: u32, r2: u32) -> ! {
+    let bss = bss();
+    let mut ptr = bss.start as *mut u32;
+    while (ptr as u32) < bss.end {
         unsafe {
             ptr.write_volatile(0);
             ptr = ptr.add(1);
         }
     }
 
+    memmap_check_layout();
+    let boot = BootInfo::parse(r0, r1, r2);
+
     unsafe {
-        notmain();
+        notmain(&boot);
     }
-    loop {} 
-}
\ No newline at end of file
+    loop {}
+}